@@ -0,0 +1,61 @@
+use crate::{ActionsForSession, SessionGuard, SessionService};
+use sails_rs::{collections::HashMap, prelude::*};
+
+// Moves made by each player, the game state the sessions authorize changes of
+static mut MOVES: Option<HashMap<ActorId, u32>> = None;
+
+// Returns the number of moves the player has made so far
+fn record_move(player: ActorId) -> u32 {
+    let moves = unsafe { &mut *core::ptr::addr_of_mut!(MOVES) }.get_or_insert_with(HashMap::new);
+    let count = moves.entry(player).or_default();
+    *count += 1;
+    *count
+}
+
+pub struct GameService(());
+
+#[service]
+impl GameService {
+    pub fn new() -> Self {
+        Self(())
+    }
+
+    // The move is made for the account whose session the sender uses, or for the sender itself
+    pub fn make_move(&mut self, session_for_account: Option<ActorId>) -> u32 {
+        let player =
+            SessionService::new().get_actor_id(session_for_account, ActionsForSession::Move);
+        record_move(player)
+    }
+
+    // The same for a call forwarded by a trusted program on behalf of `original_sender`
+    pub fn forwarded_move(
+        &mut self,
+        original_sender: ActorId,
+        session_for_account: ActorId,
+    ) -> u32 {
+        let player = SessionService::new().get_forwarded_actor_id(
+            original_sender,
+            session_for_account,
+            ActionsForSession::Move,
+        );
+        record_move(player)
+    }
+
+    // A method checking the session at several points, the move is authorized only once
+    // whether it's checked by the guard or by `get_actor_id`
+    pub fn move_and_skip(&mut self, session_for_account: Option<ActorId>) -> u32 {
+        let guard = SessionGuard::resolve(session_for_account, ActionsForSession::Move);
+        guard.require(ActionsForSession::Move);
+        let player =
+            SessionService::new().get_actor_id(session_for_account, ActionsForSession::Move);
+        guard.require(ActionsForSession::Skip);
+        record_move(player)
+    }
+
+    pub fn moves(&self, player: ActorId) -> u32 {
+        unsafe { &*core::ptr::addr_of!(MOVES) }
+            .as_ref()
+            .and_then(|moves| moves.get(&player).copied())
+            .unwrap_or_default()
+    }
+}
@@ -2,6 +2,11 @@
 
 use sails_rs::prelude::*;
 use session_service::*;
+
+mod game;
+
+use game::GameService;
+
 pub struct SessionsProgram(());
 
 #[program]
@@ -14,6 +19,10 @@ impl SessionsProgram {
     pub fn session(&self) -> SessionService {
         SessionService::new()
     }
+
+    pub fn game(&self) -> GameService {
+        GameService::new()
+    }
 }
 
 #[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
@@ -25,4 +34,4 @@ pub enum ActionsForSession {
     Skip,
 }
 
-generate_session_system!(ActionsForSession);
+generate_session_system!(ActionsForSession, read_only: [ActionsForSession::Skip]);
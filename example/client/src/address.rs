@@ -0,0 +1,132 @@
+use blake2::{Blake2b512, Digest};
+use sails_rs::prelude::*;
+
+pub const SUBSTRATE_SS58_PREFIX: u16 = 42;
+pub const VARA_SS58_PREFIX: u16 = 137;
+
+const SS58_CHECKSUM_PREFIX: &[u8] = b"SS58PRE";
+const CHECKSUM_LENGTH: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressError {
+    InvalidFormat,
+    InvalidChecksum,
+    WrongNetwork { expected: u16, found: u16 },
+}
+
+// An account given in any of the formats integrators come with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Address<'a> {
+    Id(ActorId),
+    // SS58 or hex encoded public key, with or without `0x`
+    Text(&'a str),
+}
+
+impl From<ActorId> for Address<'_> {
+    fn from(id: ActorId) -> Self {
+        Self::Id(id)
+    }
+}
+
+impl From<[u8; 32]> for Address<'_> {
+    fn from(public_key: [u8; 32]) -> Self {
+        Self::Id(public_key.into())
+    }
+}
+
+impl<'a> From<&'a str> for Address<'a> {
+    fn from(address: &'a str) -> Self {
+        Self::Text(address)
+    }
+}
+
+impl Address<'_> {
+    // `network` is the SS58 prefix the address must be encoded for, `None` accepts any.
+    // Hex keys and ids don't carry a network, so they are accepted as is
+    pub fn to_actor_id(&self, network: Option<u16>) -> Result<ActorId, AddressError> {
+        match self {
+            Self::Id(id) => Ok(*id),
+            Self::Text(address) => parse_address(address, network),
+        }
+    }
+}
+
+pub fn parse_address(address: &str, network: Option<u16>) -> Result<ActorId, AddressError> {
+    let address = address.trim();
+    match parse_hex(address) {
+        Some(public_key) => Ok(public_key.into()),
+        None => parse_ss58(address, network),
+    }
+}
+
+pub fn to_ss58(account: ActorId, network: u16) -> String {
+    let public_key: [u8; 32] = account.into();
+    let mut data = ss58_prefix(network);
+    data.extend_from_slice(&public_key);
+    let checksum = ss58_checksum(&data);
+    data.extend_from_slice(&checksum[..CHECKSUM_LENGTH]);
+    bs58::encode(data).into_string()
+}
+
+fn parse_hex(address: &str) -> Option<[u8; 32]> {
+    let digits = address.strip_prefix("0x").unwrap_or(address).as_bytes();
+    // `from_str_radix` alone would also take a sign in front of a digit
+    if digits.len() != 64 || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let mut public_key = [0; 32];
+    for (byte, pair) in public_key.iter_mut().zip(digits.chunks(2)) {
+        let pair = core::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(public_key)
+}
+
+fn parse_ss58(address: &str, network: Option<u16>) -> Result<ActorId, AddressError> {
+    let data = bs58::decode(address)
+        .into_vec()
+        .map_err(|_| AddressError::InvalidFormat)?;
+    let (found, prefix_length) = match data.first() {
+        Some(&first) if first < 64 => (first as u16, 1),
+        Some(&first) if first < 128 && data.len() > 1 => {
+            let second = data[1];
+            let lower = ((first & 0b0011_1111) << 2) | (second >> 6);
+            let upper = second & 0b0011_1111;
+            (lower as u16 | (upper as u16) << 8, 2)
+        }
+        _ => return Err(AddressError::InvalidFormat),
+    };
+    if data.len() != prefix_length + 32 + CHECKSUM_LENGTH {
+        return Err(AddressError::InvalidFormat);
+    }
+
+    let (payload, checksum) = data.split_at(prefix_length + 32);
+    if ss58_checksum(payload)[..CHECKSUM_LENGTH] != *checksum {
+        return Err(AddressError::InvalidChecksum);
+    }
+    if let Some(expected) = network.filter(|expected| *expected != found) {
+        return Err(AddressError::WrongNetwork { expected, found });
+    }
+
+    let mut public_key = [0; 32];
+    public_key.copy_from_slice(&payload[prefix_length..]);
+    Ok(public_key.into())
+}
+
+fn ss58_prefix(network: u16) -> Vec<u8> {
+    match network {
+        0..=63 => vec![network as u8],
+        _ => {
+            let first = ((network & 0b1111_1100) >> 2) as u8 | 0b0100_0000;
+            let second = (network >> 8) as u8 | ((network & 0b0000_0011) << 6) as u8;
+            vec![first, second]
+        }
+    }
+}
+
+fn ss58_checksum(data: &[u8]) -> [u8; 64] {
+    let mut hasher = Blake2b512::new();
+    hasher.update(SS58_CHECKSUM_PREFIX);
+    hasher.update(data);
+    hasher.finalize().into()
+}
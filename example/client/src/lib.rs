@@ -2,3 +2,28 @@
 
 // Incorporate code generated based on the IDL file
 include!(concat!(env!("OUT_DIR"), "/sessions_client.rs"));
+
+mod address;
+
+pub use address::*;
+
+impl SignatureData {
+    // Accepts the key as an `ActorId`, a hex public key or an SS58 address of `network`,
+    // `signature_valid_until` is left for the signed paths to set
+    pub fn new<'a>(
+        key: impl Into<Address<'a>>,
+        duration: u64,
+        allowed_actions: Vec<ActionsForSession>,
+        network: Option<u16>,
+    ) -> Result<Self, AddressError> {
+        Ok(Self {
+            key: key.into().to_actor_id(network)?,
+            duration,
+            allowed_actions,
+            scope_id: None,
+            action_cooldowns: Vec::new(),
+            duration_blocks: 0,
+            signature_valid_until: 0,
+        })
+    }
+}
@@ -0,0 +1,39 @@
+#![no_std]
+
+// A minimal eligibility registry for the tests. It's initialized with the accounts it knows
+// and whether each of them is eligible, replies with that to the encoded `ActorId`
+// of an owner and panics on the accounts it doesn't know
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use gstd::{msg, prelude::*, ActorId};
+
+    static mut ACCOUNTS: Vec<(ActorId, bool)> = Vec::new();
+
+    #[no_mangle]
+    extern "C" fn init() {
+        let accounts: Vec<(ActorId, bool)> = msg::load().expect("Failed to decode the accounts");
+        unsafe { *core::ptr::addr_of_mut!(ACCOUNTS) = accounts };
+    }
+
+    #[no_mangle]
+    extern "C" fn handle() {
+        let owner: ActorId = msg::load().expect("Failed to decode the owner");
+        let accounts = unsafe { &*core::ptr::addr_of!(ACCOUNTS) };
+        let (_, eligible) = accounts
+            .iter()
+            .find(|(account, _)| *account == owner)
+            .expect("The account is unknown");
+        msg::reply(*eligible, 0).expect("Failed to reply");
+    }
+}
+
+#[cfg(feature = "wasm-binary")]
+#[cfg(not(target_arch = "wasm32"))]
+pub use code::WASM_BINARY_OPT as WASM_BINARY;
+
+#[cfg(feature = "wasm-binary")]
+#[cfg(not(target_arch = "wasm32"))]
+mod code {
+    include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
+}
@@ -0,0 +1,43 @@
+#![no_std]
+
+// The sessions program with the service exposed under the `Sessions` route,
+// used by the tests to check that the route passed to the macro is the one deletions use
+
+use sails_rs::prelude::*;
+use session_service::*;
+
+pub struct RoutedProgram(());
+
+#[program]
+impl RoutedProgram {
+    pub async fn new(config: Config) -> Self {
+        SessionService::init(config);
+        Self(())
+    }
+
+    pub fn sessions(&self) -> SessionService {
+        SessionService::new()
+    }
+}
+
+// The same actions as in `sessions-app`, so that its client types can be reused
+#[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
+#[codec(crate = sails_rs::scale_codec)]
+#[scale_info(crate = sails_rs::scale_info)]
+pub enum ActionsForSession {
+    StartGame,
+    Move,
+    Skip,
+}
+
+generate_session_system!(ActionsForSession, service_route: "Sessions");
+
+#[cfg(feature = "wasm-binary")]
+#[cfg(not(target_arch = "wasm32"))]
+pub use code::WASM_BINARY_OPT as WASM_BINARY;
+
+#[cfg(feature = "wasm-binary")]
+#[cfg(not(target_arch = "wasm32"))]
+mod code {
+    include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
+}
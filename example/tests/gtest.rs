@@ -1,49 +1,99 @@
-use sails_rs::{calls::*, gtest::calls::*, prelude::*};
+use sails_rs::{
+    calls::*,
+    gtest::{calls::*, Log},
+    prelude::*,
+};
 
 use rand_core::OsRng;
 use schnorrkel::Keypair;
-use sessions_client::{traits::*, ActionsForSession, Config, SignatureData};
+use sessions_client::{
+    parse_address, to_ss58, traits::*, ActionsForSession, AddressError, Config, SessionData,
+    SessionError, SessionTemplate, SignatureData, SpectatorData, SUBSTRATE_SS58_PREFIX,
+    VARA_SS58_PREFIX,
+};
 
 const ACTOR_ID: u64 = 42;
 
-#[tokio::test]
-async fn create_session_works() {
-    let remoting = GTestRemoting::new(ACTOR_ID.into());
-    remoting.system().init_logger();
-
-    // Submit program code into the system
-    let program_code_id = remoting.system().submit_code(sessions::WASM_BINARY);
-
-    let program_factory = sessions_client::SessionsFactory::new(remoting.clone());
-
-    let config = Config {
+fn config() -> Config {
+    Config {
         gas_to_delete_session: 10_000_000_000,
         minimum_session_duration_ms: 180_000,
         ms_per_block: 3_000,
-    };
+        max_attempts_per_block: 10,
+        deposit_per_byte: 0,
+        require_key_signature: false,
+        eligibility_registry: None,
+        notify_owners: false,
+        heartbeat_interval_blocks: 0,
+        sliding_expiration_ms: 0,
+        max_session_lifetime_ms: 0,
+        reauth_window_blocks: 0,
+        deletion_gas_margin_percent: 0,
+    }
+}
+
+// Deploys the program from `ACTOR_ID`, which becomes its admin
+async fn deploy(config: Config) -> (GTestRemoting, ActorId) {
+    let remoting = GTestRemoting::new(ACTOR_ID.into());
+    remoting.system().init_logger();
 
-    let program_id = program_factory
+    let program_code_id = remoting.system().submit_code(sessions::WASM_BINARY);
+    let program_id = sessions_client::SessionsFactory::new(remoting.clone())
         .new(config)
         .send_recv(program_code_id, b"salt")
         .await
         .unwrap();
 
+    (remoting, program_id)
+}
+
+fn signature_data_for(key: u64, allowed_actions: Vec<ActionsForSession>) -> SignatureData {
+    SignatureData {
+        key: key.into(),
+        duration: 180_000,
+        allowed_actions,
+        scope_id: None,
+        action_cooldowns: vec![],
+        duration_blocks: 0,
+        signature_valid_until: 0,
+    }
+}
+
+// An hour from the current block, for the data signed in the tests
+fn signature_valid_until(remoting: &GTestRemoting) -> u64 {
+    remoting.system().block_timestamp() + 3_600_000
+}
+
+// Signs the payload the way wallets sign raw bytes
+fn sign(pair: &Keypair, payload: impl Encode) -> Vec<u8> {
+    let complete_message = [b"<Bytes>".to_vec(), payload.encode(), b"</Bytes>".to_vec()].concat();
+
+    pair.sign_simple(b"substrate", &complete_message)
+        .to_bytes()
+        .to_vec()
+}
+
+#[tokio::test]
+async fn create_session_works() {
+    let (remoting, program_id) = deploy(config()).await;
+
     let mut service_client = sessions_client::Session::new(remoting.clone());
 
     let key = 10;
 
-    let signature_data = SignatureData {
-        key: key.into(),
-        duration: 180_000,
-        allowed_actions: vec![ActionsForSession::StartGame, ActionsForSession::Move],
-    };
+    let signature_data = signature_data_for(
+        key,
+        vec![ActionsForSession::StartGame, ActionsForSession::Move],
+    );
 
     let result = service_client
         .create_session(signature_data, None)
         .send_recv(program_id)
         .await;
 
-    assert!(result.is_ok());
+    let (account, created) = result.unwrap().unwrap();
+    assert_eq!(account, ActorId::from(ACTOR_ID));
+    assert_eq!(created.key, ActorId::from(key));
 
     // check session in state
     let result = service_client
@@ -52,23 +102,30 @@ async fn create_session_works() {
         .await
         .unwrap();
 
-    assert!(result.is_some());
+    assert_eq!(
+        result.map(|session| session.expires_at_block),
+        Some(created.expires_at_block)
+    );
+
+    // an account with an active session can't be a session key of another account
+    let result = service_client
+        .delete_session_from_account()
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
 
     // create session with signature
     let pair: Keypair = Keypair::generate_with(OsRng);
+    let valid_until = signature_valid_until(&remoting);
     let data_to_sign = SignatureData {
-        key: ACTOR_ID.into(),
-        duration: 180_000,
-        allowed_actions: vec![ActionsForSession::StartGame, ActionsForSession::Move],
+        signature_valid_until: valid_until,
+        ..signature_data_for(
+            ACTOR_ID,
+            vec![ActionsForSession::StartGame, ActionsForSession::Move],
+        )
     };
-    let complete_message = [
-        b"<Bytes>".to_vec(),
-        data_to_sign.encode(),
-        b"</Bytes>".to_vec(),
-    ]
-    .concat();
-
-    let raw_signature = pair.sign_simple(b"substrate", &complete_message).to_bytes();
+    let raw_signature = sign(&pair, data_to_sign);
 
     let key = ActorId::from(pair.public.to_bytes());
 
@@ -76,14 +133,18 @@ async fn create_session_works() {
         key,
         duration: 180_000,
         allowed_actions: vec![ActionsForSession::StartGame, ActionsForSession::Move],
+        scope_id: None,
+        action_cooldowns: vec![],
+        duration_blocks: 0,
+        signature_valid_until: valid_until,
     };
 
     let result = service_client
-        .create_session(signature_data, Some(raw_signature.to_vec()))
+        .create_session(signature_data, Some(raw_signature))
         .send_recv(program_id)
         .await;
 
-    assert!(result.is_ok());
+    assert!(result.unwrap().is_ok());
 
     // check session in state
     let result = service_client
@@ -97,25 +158,7 @@ async fn create_session_works() {
 
 #[tokio::test]
 async fn create_session_failures() {
-    let remoting = GTestRemoting::new(ACTOR_ID.into());
-    remoting.system().init_logger();
-
-    // Submit program code into the system
-    let program_code_id = remoting.system().submit_code(sessions::WASM_BINARY);
-
-    let program_factory = sessions_client::SessionsFactory::new(remoting.clone());
-
-    let config = Config {
-        gas_to_delete_session: 10_000_000_000,
-        minimum_session_duration_ms: 180_000,
-        ms_per_block: 3_000,
-    };
-
-    let program_id = program_factory
-        .new(config)
-        .send_recv(program_code_id, b"salt")
-        .await
-        .unwrap();
+    let (remoting, program_id) = deploy(config()).await;
 
     let mut service_client = sessions_client::Session::new(remoting.clone());
 
@@ -126,6 +169,10 @@ async fn create_session_failures() {
         key: key.into(),
         duration: 179_000,
         allowed_actions: vec![ActionsForSession::StartGame, ActionsForSession::Move],
+        scope_id: None,
+        action_cooldowns: vec![],
+        duration_blocks: 0,
+        signature_valid_until: 0,
     };
 
     let result = service_client
@@ -133,13 +180,23 @@ async fn create_session_failures() {
         .send_recv(program_id)
         .await;
 
-    assert!(result.is_err());
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::DurationIsSmall {
+            provided: 179_000,
+            required: 180_000,
+        })
+    ));
 
     // duration id too long (more than 400 years)
     let signature_data = SignatureData {
         key: key.into(),
         duration: 12884901888000,
         allowed_actions: vec![ActionsForSession::StartGame, ActionsForSession::Move],
+        scope_id: None,
+        action_cooldowns: vec![],
+        duration_blocks: 0,
+        signature_valid_until: 0,
     };
 
     let result = service_client
@@ -147,81 +204,197 @@ async fn create_session_failures() {
         .send_recv(program_id)
         .await;
 
-    assert!(result.is_err());
+    assert!(result.unwrap().is_err());
 
     // there are no allowed actions
-    let signature_data = SignatureData {
-        key: key.into(),
-        duration: 180_000,
-        allowed_actions: vec![],
-    };
+    let signature_data = signature_data_for(key, vec![]);
 
     let result = service_client
         .create_session(signature_data, None)
         .send_recv(program_id)
         .await;
 
-    assert!(result.is_err());
+    assert!(result.unwrap().is_err());
 
-    // the session already exists
+    // allowed actions contain duplicates
+    let signature_data =
+        signature_data_for(key, vec![ActionsForSession::Move, ActionsForSession::Move]);
+
+    let result = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_err());
+
+    // allowed actions are not in the canonical order
+    let signature_data = signature_data_for(
+        key,
+        vec![ActionsForSession::Move, ActionsForSession::StartGame],
+    );
+
+    let result = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_err());
+
+    // the signature has a wrong length
+    let mut signature_data = signature_data_for(
+        11,
+        vec![ActionsForSession::StartGame, ActionsForSession::Move],
+    );
+    signature_data.signature_valid_until = signature_valid_until(&remoting);
+
+    let result = service_client
+        .create_session(signature_data, Some(vec![0; 65]))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_err());
+
+    // the account of the signed path is not a valid public key
     let signature_data = SignatureData {
-        key: key.into(),
+        key: ActorId::zero(),
         duration: 180_000,
         allowed_actions: vec![ActionsForSession::StartGame, ActionsForSession::Move],
+        scope_id: None,
+        action_cooldowns: vec![],
+        duration_blocks: 0,
+        signature_valid_until: signature_valid_until(&remoting),
     };
 
+    let result = service_client
+        .create_session(signature_data, Some(vec![0; 64]))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_err());
+
+    // the session key is the account itself
+    let signature_data = signature_data_for(
+        ACTOR_ID,
+        vec![ActionsForSession::StartGame, ActionsForSession::Move],
+    );
+
     let result = service_client
         .create_session(signature_data, None)
         .send_recv(program_id)
         .await;
 
-    assert!(result.is_ok());
+    assert!(result.unwrap().is_err());
+
+    // the session already exists
+    let signature_data = signature_data_for(
+        key,
+        vec![ActionsForSession::StartGame, ActionsForSession::Move],
+    );
+
+    let result = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let signature_data = signature_data_for(
+        key,
+        vec![ActionsForSession::StartGame, ActionsForSession::Move],
+    );
+
+    let result = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_err());
+
+    // the account with an active session is used as a session key
+    let pair: Keypair = Keypair::generate_with(OsRng);
+    let valid_until = signature_valid_until(&remoting);
+    let data_to_sign = SignatureData {
+        signature_valid_until: valid_until,
+        ..signature_data_for(
+            ACTOR_ID,
+            vec![ActionsForSession::StartGame, ActionsForSession::Move],
+        )
+    };
+    let raw_signature = sign(&pair, data_to_sign);
 
     let signature_data = SignatureData {
-        key: key.into(),
+        key: ActorId::from(pair.public.to_bytes()),
         duration: 180_000,
         allowed_actions: vec![ActionsForSession::StartGame, ActionsForSession::Move],
+        scope_id: None,
+        action_cooldowns: vec![],
+        duration_blocks: 0,
+        signature_valid_until: valid_until,
     };
 
     let result = service_client
-        .create_session(signature_data, None)
+        .create_session(signature_data, Some(raw_signature))
         .send_recv(program_id)
         .await;
 
-    assert!(result.is_err())
+    assert!(result.unwrap().is_err())
 }
 
 #[tokio::test]
 async fn delete_session_from_account_works() {
-    let remoting = GTestRemoting::new(ACTOR_ID.into());
-    remoting.system().init_logger();
+    let (remoting, program_id) = deploy(config()).await;
 
-    // Submit program code into the system
-    let program_code_id = remoting.system().submit_code(sessions::WASM_BINARY);
+    let mut service_client = sessions_client::Session::new(remoting.clone());
 
-    let program_factory = sessions_client::SessionsFactory::new(remoting.clone());
+    // duration is less than minimum session duration
+    let key = 10;
 
-    let config = Config {
-        gas_to_delete_session: 10_000_000_000,
-        minimum_session_duration_ms: 180_000,
-        ms_per_block: 3_000,
-    };
+    let signature_data = signature_data_for(
+        key,
+        vec![ActionsForSession::StartGame, ActionsForSession::Move],
+    );
 
-    let program_id = program_factory
-        .new(config)
-        .send_recv(program_code_id, b"salt")
+    let result = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let result = service_client
+        .delete_session_from_account()
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    // check state
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
         .await
         .unwrap();
 
+    assert!(result.is_none());
+}
+
+#[tokio::test]
+async fn invalidate_scope_works() {
+    // the initializer of the program is the admin
+    let (remoting, program_id) = deploy(config()).await;
+
     let mut service_client = sessions_client::Session::new(remoting.clone());
 
-    // duration is less than minimum session duration
     let key = 10;
 
     let signature_data = SignatureData {
         key: key.into(),
         duration: 180_000,
         allowed_actions: vec![ActionsForSession::StartGame, ActionsForSession::Move],
+        scope_id: Some(7),
+        action_cooldowns: vec![],
+        duration_blocks: 0,
+        signature_valid_until: 0,
     };
 
     let result = service_client
@@ -229,10 +402,10 @@ async fn delete_session_from_account_works() {
         .send_recv(program_id)
         .await;
 
-    assert!(result.is_ok());
+    assert!(result.unwrap().is_ok());
 
     let result = service_client
-        .delete_session_from_account()
+        .invalidate_scope(7)
         .send_recv(program_id)
         .await;
 
@@ -246,4 +419,2819 @@ async fn delete_session_from_account_works() {
         .unwrap();
 
     assert!(result.is_none());
+
+    // no session can be created for the invalidated scope
+    let signature_data = SignatureData {
+        key: key.into(),
+        duration: 180_000,
+        allowed_actions: vec![ActionsForSession::StartGame, ActionsForSession::Move],
+        scope_id: Some(7),
+        action_cooldowns: vec![],
+        duration_blocks: 0,
+        signature_valid_until: 0,
+    };
+
+    let result = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::ScopeIsInvalidated)
+    ));
+}
+
+#[tokio::test]
+async fn session_is_deleted_by_delayed_message() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let key = 10;
+
+    let signature_data = signature_data_for(
+        key,
+        vec![ActionsForSession::StartGame, ActionsForSession::Move],
+    );
+
+    let result = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let session = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap()
+        .expect("Session must exist");
+
+    // the deletion can't be triggered manually before the delayed message arrives
+    let result = service_client
+        .delete_session_from_program(ACTOR_ID.into(), session.generation)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_err());
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_some());
+
+    // run blocks until the scheduled deletion is executed
+    let block_results = remoting.system().run_to_block(session.expires_at_block);
+
+    let session_deleted = ["Session".encode(), "SessionDeleted".encode()].concat();
+    let event_emitted = block_results
+        .iter()
+        .flat_map(|block_result| block_result.log())
+        .any(|log| log.source() == program_id && log.payload().starts_with(&session_deleted));
+
+    assert!(event_emitted);
+
+    // check state
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+}
+
+#[tokio::test]
+async fn stale_delayed_deletion_is_ignored() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let key = 10;
+
+    let signature_data = signature_data_for(
+        key,
+        vec![ActionsForSession::StartGame, ActionsForSession::Move],
+    );
+
+    let result = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let first_session = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap()
+        .expect("Session must exist");
+
+    let result = service_client
+        .delete_session_from_account()
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    // re-create the session for a longer period
+    let signature_data = SignatureData {
+        key: key.into(),
+        duration: 360_000,
+        allowed_actions: vec![ActionsForSession::StartGame, ActionsForSession::Move],
+        scope_id: None,
+        action_cooldowns: vec![],
+        duration_blocks: 0,
+        signature_valid_until: 0,
+    };
+
+    let result = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    // the deletion scheduled for the first session must not remove the new one
+    remoting
+        .system()
+        .run_to_block(first_session.expires_at_block);
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap()
+        .expect("Session must exist");
+
+    assert_ne!(result.generation, first_session.generation);
+}
+
+#[tokio::test]
+async fn deletion_is_rescheduled_when_blocks_are_faster() {
+    // blocks of the test runtime come every 3 seconds, twice as fast as configured
+    let config = Config {
+        ms_per_block: 6_000,
+        ..config()
+    };
+    let (remoting, program_id) = deploy(config).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let signature_data = signature_data_for(10, vec![ActionsForSession::StartGame]);
+
+    let (_, session) = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // the delayed deletion arrives after only half of the duration
+    remoting.system().run_to_block(session.expires_at_block);
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_some());
+
+    let result = service_client
+        .sessions_without_deletion()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_empty());
+
+    // the retries converge on the block the timestamp is reached in
+    remoting
+        .system()
+        .run_to_block(session.created_at_block + 61);
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+}
+
+#[tokio::test]
+async fn signature_is_not_replayed_after_expiration() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    // the session key `ACTOR_ID` submits the signature of the owner
+    let pair: Keypair = Keypair::generate_with(OsRng);
+    let mut signature_data = signature_data_for(ACTOR_ID, vec![ActionsForSession::StartGame]);
+    signature_data.signature_valid_until = signature_valid_until(&remoting);
+    let raw_signature = sign(&pair, signature_data.clone());
+
+    let (_, session) = service_client
+        .create_session(signature_data.clone(), Some(raw_signature.clone()))
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    remoting.system().run_to_block(session.expires_at_block);
+
+    let result = service_client
+        .session_for_the_account(pair.public.to_bytes().into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+
+    let result = service_client
+        .create_session(signature_data, Some(raw_signature))
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::SignatureAlreadyUsed)
+    ));
+}
+
+#[tokio::test]
+async fn attempts_are_limited_per_block() {
+    let config = Config {
+        max_attempts_per_block: 1,
+        ..config()
+    };
+    let (remoting, program_id) = deploy(config).await;
+
+    // both messages are executed in the same block
+    let remoting = remoting.with_block_run_mode(BlockRunMode::Manual);
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    // a failed attempt is counted as well
+    let mut signature_data = signature_data_for(10, vec![ActionsForSession::StartGame]);
+    signature_data.duration = 179_000;
+    let first = service_client
+        .create_session(signature_data, None)
+        .send(program_id)
+        .await
+        .unwrap();
+
+    let second = service_client
+        .request_session(signature_data_for(43, vec![ActionsForSession::StartGame]))
+        .send(program_id)
+        .await
+        .unwrap();
+
+    remoting.run_next_block();
+
+    assert!(matches!(
+        first.recv().await.unwrap(),
+        Err(SessionError::DurationIsSmall { .. })
+    ));
+    assert!(matches!(
+        second.recv().await.unwrap(),
+        Err(SessionError::TooManyAttempts { limit: 1 })
+    ));
+
+    // the limit is reset in the next block
+    let request = service_client
+        .request_session(signature_data_for(43, vec![ActionsForSession::StartGame]))
+        .send(program_id)
+        .await
+        .unwrap();
+
+    remoting.run_next_block();
+
+    assert!(request.recv().await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn deposit_is_required_and_returned() {
+    let config = Config {
+        deposit_per_byte: 10_000_000_000,
+        ..config()
+    };
+    let (remoting, program_id) = deploy(config).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let signature_data = signature_data_for(10, vec![ActionsForSession::StartGame]);
+
+    let result = service_client
+        .create_session(signature_data.clone(), None)
+        .send_recv(program_id)
+        .await;
+
+    let Err(SessionError::InsufficientDeposit { provided, required }) = result.unwrap() else {
+        panic!("the deposit must be required");
+    };
+    assert_eq!(provided, 0);
+
+    let balance = remoting.system().balance_of(program_id);
+
+    let result = service_client
+        .create_session(signature_data, None)
+        .with_value(required)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+    assert_eq!(remoting.system().balance_of(program_id), balance + required);
+
+    // the deposit is sent back with the deletion
+    let result = service_client
+        .delete_session_from_account()
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+    assert_eq!(remoting.system().balance_of(program_id), balance);
+}
+
+#[tokio::test]
+async fn pending_deletion_is_adopted_by_later_session() {
+    let config = Config {
+        deposit_per_byte: 10_000_000_000,
+        ..config()
+    };
+    let (remoting, program_id) = deploy(config).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let mut signature_data = signature_data_for(10, vec![ActionsForSession::StartGame]);
+    signature_data.duration = 240_000;
+
+    let result = service_client
+        .create_session(signature_data.clone(), None)
+        .send_recv(program_id)
+        .await;
+
+    let Err(SessionError::InsufficientDeposit { required, .. }) = result.unwrap() else {
+        panic!("the deposit must be required");
+    };
+
+    let balance = remoting.system().balance_of(program_id);
+
+    let (_, first_session) = service_client
+        .create_session(signature_data, None)
+        .with_value(required)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let result = service_client
+        .delete_session_from_account()
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    // the session expiring 10 blocks earlier takes over the deletion of the first one
+    let mut signature_data = signature_data_for(10, vec![ActionsForSession::StartGame]);
+    signature_data.duration = 210_000;
+
+    let (_, session) = service_client
+        .create_session(signature_data, None)
+        .with_value(required)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_ne!(session.generation, first_session.generation);
+    assert!(first_session.expires_at_block - session.expires_at_block <= 20);
+
+    let pending = service_client
+        .pending_deletions()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].at_block, first_session.expires_at_block);
+    assert_eq!(pending[0].generation, session.generation);
+    assert!(!pending[0].orphaned);
+
+    // the deposit is kept until the adopted deletion is executed
+    remoting.system().run_to_block(session.expires_at_block);
+
+    assert_eq!(remoting.system().balance_of(program_id), balance + required);
+
+    remoting
+        .system()
+        .run_to_block(first_session.expires_at_block);
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+    assert_eq!(remoting.system().balance_of(program_id), balance);
+
+    // a deletion due more than `ADOPTED_DELETION_SLACK_BLOCKS` after the expiry isn't adopted
+    let mut signature_data = signature_data_for(10, vec![ActionsForSession::StartGame]);
+    signature_data.duration = 360_000;
+
+    let (_, first_session) = service_client
+        .create_session(signature_data, None)
+        .with_value(required)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let result = service_client
+        .delete_session_from_account()
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let signature_data = signature_data_for(10, vec![ActionsForSession::StartGame]);
+
+    let (_, session) = service_client
+        .create_session(signature_data, None)
+        .with_value(required)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let pending = service_client
+        .pending_deletions()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(pending.len(), 2);
+    assert!(pending
+        .iter()
+        .any(|deletion| deletion.orphaned && deletion.at_block == first_session.expires_at_block));
+
+    // so the deposit comes back as soon as the session expires
+    remoting.system().run_to_block(session.expires_at_block);
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+    assert_eq!(remoting.system().balance_of(program_id), balance);
+}
+
+#[tokio::test]
+async fn key_signature_is_required() {
+    let config = Config {
+        require_key_signature: true,
+        ..config()
+    };
+    let (remoting, program_id) = deploy(config).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let pair: Keypair = Keypair::generate_with(OsRng);
+    let mut signature_data = signature_data_for(ACTOR_ID, vec![ActionsForSession::StartGame]);
+    signature_data.signature_valid_until = signature_valid_until(&remoting);
+    // the key signs the data with the owner in place of itself
+    let key_signature = sign(&pair, signature_data.clone());
+    signature_data.key = pair.public.to_bytes().into();
+
+    let result = service_client
+        .create_session(signature_data.clone(), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::KeySignatureRequired)
+    ));
+
+    let result = service_client
+        .create_session_signed_by_key(signature_data, key_signature)
+        .send_recv(program_id)
+        .await;
+
+    let (account, session) = result.unwrap().unwrap();
+    assert_eq!(account, ActorId::from(ACTOR_ID));
+    assert_eq!(session.key, ActorId::from(pair.public.to_bytes()));
+}
+
+#[tokio::test]
+async fn team_session_acts_for_the_team() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut game_client = sessions_client::Game::new(remoting.clone());
+
+    let mut pairs: Vec<Keypair> = (0..2).map(|_| Keypair::generate_with(OsRng)).collect();
+    pairs.sort_by_key(|pair| pair.public.to_bytes());
+    let members: Vec<ActorId> = pairs
+        .iter()
+        .map(|pair| pair.public.to_bytes().into())
+        .collect();
+
+    let team = service_client
+        .team_id(members.clone())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    // each member signs the data with the session key `ACTOR_ID` in place of `key`
+    let mut signature_data = signature_data_for(ACTOR_ID, vec![ActionsForSession::Move]);
+    signature_data.signature_valid_until = signature_valid_until(&remoting);
+    let signatures = pairs
+        .iter()
+        .map(|pair| sign(pair, (signature_data.clone(), members.clone())))
+        .collect();
+    signature_data.key = team;
+
+    let (account, _) = service_client
+        .create_team_session(signature_data, members, signatures)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(account, team);
+
+    // the move is attributed to the team rather than to the key
+    let moves = game_client
+        .make_move(Some(team))
+        .send_recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(moves, 1);
+
+    let result = game_client
+        .moves(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(result, 0);
+
+    // only the key of the team can act for it
+    let mut game_client = sessions_client::Game::new(remoting.clone().with_actor_id(43.into()));
+
+    let result = game_client
+        .make_move(Some(team))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+
+    // the key isn't a member, so it can't end the session of the team
+    let result = service_client
+        .delete_team_session(team)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn spectator_session_allows_read_only_actions() {
+    // spectator sessions don't need the signature of the key
+    let config = Config {
+        require_key_signature: true,
+        ..config()
+    };
+    let (remoting, program_id) = deploy(config).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let spectator_data = SpectatorData {
+        key: 43.into(),
+        duration: 180_000,
+        signature_valid_until: 0,
+    };
+
+    let (_, session) = service_client
+        .create_spectator_session(spectator_data, None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(session.allowed_actions, vec![ActionsForSession::Skip]);
+
+    // the key can't make a move with it
+    let mut game_client = sessions_client::Game::new(remoting.clone().with_actor_id(43.into()));
+
+    let result = game_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn session_is_created_from_template() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let template = SessionTemplate {
+        allowed_actions: vec![ActionsForSession::Move],
+        duration: 180_000,
+        scope_id: None,
+        action_cooldowns: vec![],
+    };
+
+    // only the admin publishes templates
+    let mut key_client = sessions_client::Session::new(remoting.clone().with_actor_id(43.into()));
+
+    let result = key_client
+        .publish_template(template.clone())
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+
+    let result = service_client
+        .publish_template(template)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    // the owner signs only `TemplateData`: the template id, the key and the validity
+    let pair: Keypair = Keypair::generate_with(OsRng);
+    let owner = ActorId::from(pair.public.to_bytes());
+    let valid_until = signature_valid_until(&remoting);
+    let signature = sign(&pair, (1u32, ActorId::from(43), valid_until));
+
+    let (account, session) = key_client
+        .create_session_from_template(1, owner, Some(signature), valid_until)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(account, owner);
+    assert_eq!(session.allowed_actions, vec![ActionsForSession::Move]);
+
+    // no session is created from a retired template
+    let result = service_client
+        .retire_template(1)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let result = service_client
+        .create_session_from_template(1, 44.into(), None, 0)
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(result.unwrap(), Err(SessionError::NoTemplate)));
+}
+
+#[tokio::test]
+async fn trusted_forwarder_acts_for_original_sender() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let result = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let result = service_client
+        .add_trusted_forwarder(45.into())
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    // the forwarder passes on the move of the session key `43`
+    let mut forwarder_client =
+        sessions_client::Game::new(remoting.clone().with_actor_id(45.into()));
+
+    let moves = forwarder_client
+        .forwarded_move(43.into(), ACTOR_ID.into())
+        .send_recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(moves, 1);
+
+    // the forwarder can't claim the call comes from anyone but the key,
+    // the owner included
+    for original_sender in [44, ACTOR_ID] {
+        let result = forwarder_client
+            .forwarded_move(original_sender.into(), ACTOR_ID.into())
+            .send_recv(program_id)
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    let moves = forwarder_client
+        .moves(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(moves, 1);
+
+    // any other program is taken at its word only for itself
+    let mut untrusted_client =
+        sessions_client::Game::new(remoting.clone().with_actor_id(44.into()));
+
+    let result = untrusted_client
+        .forwarded_move(43.into(), ACTOR_ID.into())
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+
+    let result = untrusted_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn sessions_are_imported_into_frozen_program() {
+    let (remoting, old_program_id) = deploy(config()).await;
+
+    let program_code_id = remoting.system().submit_code(sessions::WASM_BINARY);
+    let program_id = sessions_client::SessionsFactory::new(remoting.clone())
+        .new(config())
+        .send_recv(program_code_id, b"new salt")
+        .await
+        .unwrap();
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let (_, session) = service_client
+        .create_session(signature_data_for(10, vec![ActionsForSession::Move]), None)
+        .send_recv(old_program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let sessions = service_client
+        .sessions()
+        .recv(old_program_id)
+        .await
+        .unwrap();
+    let teams = service_client.teams().recv(old_program_id).await.unwrap();
+
+    // the program has to be frozen for the import
+    let result = service_client
+        .import_sessions(old_program_id, sessions.clone(), teams.clone())
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+
+    let result = service_client.freeze().send_recv(program_id).await;
+
+    assert!(result.is_ok());
+
+    let result = service_client
+        .import_sessions(old_program_id, sessions, teams)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap()
+        .expect("Session must be imported");
+
+    assert_eq!(result.key, session.key);
+    assert_eq!(result.expires, session.expires);
+}
+
+#[tokio::test]
+async fn sessions_are_found_by_label() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let result = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let result = service_client
+        .set_session_label(ACTOR_ID.into(), "laptop".into())
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let result = service_client
+        .sessions_by_label(ACTOR_ID.into(), "laptop".into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].0, ActorId::from(ACTOR_ID));
+
+    let result = service_client
+        .sessions_by_label(ACTOR_ID.into(), "phone".into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_empty());
+
+    // the session key can't label the session
+    let mut key_client = sessions_client::Session::new(remoting.clone().with_actor_id(43.into()));
+
+    let result = key_client
+        .set_session_label(ACTOR_ID.into(), "phone".into())
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+
+    // longer than `MAX_LABEL_LENGTH` of 32 bytes
+    let result = service_client
+        .set_session_label(ACTOR_ID.into(), "x".repeat(33))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn owner_is_notified_about_use_and_expiry() {
+    let config = Config {
+        notify_owners: true,
+        ..config()
+    };
+    let (remoting, program_id) = deploy(config).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut game_client = sessions_client::Game::new(remoting.clone().with_actor_id(43.into()));
+
+    let (_, session) = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let result = game_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    // `SessionNotification::FirstUse { key }`
+    let first_use = [vec![0], ActorId::from(43).encode()].concat();
+    let notification = Log::builder()
+        .source(program_id)
+        .dest(ACTOR_ID)
+        .payload_bytes(first_use);
+
+    assert!(remoting
+        .system()
+        .get_mailbox(ACTOR_ID)
+        .contains(&notification));
+
+    // `SessionNotification::Expired`
+    let block_results = remoting.system().run_to_block(session.expires_at_block);
+
+    let expired = block_results
+        .iter()
+        .flat_map(|block_result| block_result.log())
+        .any(|log| {
+            log.source() == program_id
+                && log.destination() == ActorId::from(ACTOR_ID)
+                && log.payload() == [3]
+        });
+
+    assert!(expired);
+}
+
+#[tokio::test]
+async fn timelocked_action_is_usable_later() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut game_client = sessions_client::Game::new(remoting.clone().with_actor_id(43.into()));
+
+    let result = service_client
+        .set_action_timelock(ActionsForSession::Move, 5)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let (_, session) = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let usable_from_block = session.created_at_block + 5;
+
+    let result = service_client
+        .why_not_allowed(ACTOR_ID.into(), 43.into(), ActionsForSession::Move)
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        result,
+        Some(SessionError::ActionIsTimelocked { usable_from_block })
+    );
+
+    let result = game_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+
+    remoting.system().run_to_block(usable_from_block);
+
+    let result = game_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn stake_is_refunded_or_slashed() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut key_client = sessions_client::Session::new(remoting.clone().with_actor_id(43.into()));
+
+    let amount = 10_000_000_000_000;
+    let balance = remoting.system().balance_of(program_id);
+
+    for slashed in [false, true] {
+        let result = service_client
+            .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+            .send_recv(program_id)
+            .await;
+
+        assert!(result.unwrap().is_ok());
+
+        // the key backs the session
+        let result = key_client
+            .stake_session(ACTOR_ID.into())
+            .with_value(amount)
+            .send_recv(program_id)
+            .await;
+
+        assert!(result.unwrap().is_ok());
+        assert_eq!(remoting.system().balance_of(program_id), balance + amount);
+
+        let stake = service_client
+            .session_stake(ACTOR_ID.into())
+            .recv(program_id)
+            .await
+            .unwrap()
+            .expect("Stake must exist");
+
+        assert_eq!(stake.staker, ActorId::from(43));
+        assert_eq!(stake.amount, amount);
+
+        // the stake goes back to the key with the deletion, or to the recipient with a slash
+        let result = if slashed {
+            service_client
+                .slash_stake(ACTOR_ID.into(), 45.into())
+                .send_recv(program_id)
+                .await
+        } else {
+            service_client
+                .delete_session_from_account()
+                .send_recv(program_id)
+                .await
+        };
+
+        assert!(result.is_ok());
+        assert_eq!(remoting.system().balance_of(program_id), balance);
+    }
+
+    let reputation = service_client
+        .key_reputation(43.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(reputation.slashes, 1);
+}
+
+#[tokio::test]
+async fn key_reputation_counts_revocations() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let result = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let result = service_client
+        .delete_session_from_account()
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let (_, session) = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // a session that runs out isn't held against the key
+    remoting.system().run_to_block(session.expires_at_block);
+
+    let reputation = service_client
+        .key_reputation(43.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(reputation.sessions, 2);
+    assert_eq!(reputation.revocations, 1);
+    assert_eq!(reputation.slashes, 0);
+}
+
+#[tokio::test]
+async fn blacklisted_key_is_rejected() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let (_, session) = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let history = service_client
+        .delegation_history(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].key, ActorId::from(43));
+    assert_eq!(history[0].first_seen_block, session.created_at_block);
+
+    // the session of the blacklisted key is ended right away
+    let result = service_client
+        .blacklist_keys(vec![43.into()])
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+
+    let result = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::KeyIsBlacklisted)
+    ));
+
+    let result = service_client
+        .unblacklist_keys(vec![43.into()])
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let result = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn histogram_counts_created_durations() {
+    let config = Config {
+        sliding_expiration_ms: 600_000,
+        max_session_lifetime_ms: 3_600_000,
+        ..config()
+    };
+    let (remoting, program_id) = deploy(config).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut other_client = sessions_client::Session::new(remoting.clone().with_actor_id(44.into()));
+    let mut game_client = sessions_client::Game::new(remoting.clone().with_actor_id(43.into()));
+
+    let result = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let mut signature_data = signature_data_for(45, vec![ActionsForSession::Move]);
+    signature_data.duration = 1_200_000;
+
+    let result = other_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    // the session is extended to 10 minutes, but still counted as created for 3
+    let result = game_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let histogram = service_client
+        .session_duration_histogram()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(histogram.durations, vec![1, 0, 1, 0, 0, 0]);
+}
+
+#[tokio::test]
+async fn heartbeat_is_emitted_with_deletion() {
+    let config = Config {
+        heartbeat_interval_blocks: 10,
+        ..config()
+    };
+    let (remoting, program_id) = deploy(config).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let (_, session) = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let block_results = remoting.system().run_to_block(session.expires_at_block);
+
+    // no session is left, one was created and one expired since the initialization
+    let heartbeat = [
+        "Session".encode(),
+        "SessionsHeartbeat".encode(),
+        (0u32, 1u32, 1u32).encode(),
+    ]
+    .concat();
+    let event_emitted = block_results
+        .iter()
+        .flat_map(|block_result| block_result.log())
+        .any(|log| log.source() == program_id && log.payload() == heartbeat);
+
+    assert!(event_emitted);
+}
+
+#[tokio::test]
+async fn session_slides_up_to_max_lifetime() {
+    let config = Config {
+        sliding_expiration_ms: 600_000,
+        max_session_lifetime_ms: 1_200_000,
+        ..config()
+    };
+    let (remoting, program_id) = deploy(config).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut game_client = sessions_client::Game::new(remoting.clone().with_actor_id(43.into()));
+
+    let (_, session) = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let result = game_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    // the move pushed the expiry 10 minutes ahead
+    let extended = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap()
+        .expect("Session must exist");
+
+    assert!(extended.expires >= session.expires + 420_000);
+    assert!(extended.expires_at_block > session.expires_at_block);
+
+    // one more deletion is scheduled at the end of the maximum lifetime
+    let lifetime_end = session.created_at_block + 400;
+    let pending = service_client
+        .pending_deletions()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        pending
+            .iter()
+            .map(|deletion| deletion.at_block)
+            .collect::<Vec<_>>(),
+        vec![session.expires_at_block, lifetime_end]
+    );
+
+    // the first deletion leaves the extended session alone
+    remoting.system().run_to_block(session.expires_at_block);
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_some());
+
+    remoting.system().run_to_block(lifetime_end);
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+}
+
+#[tokio::test]
+async fn disabled_action_is_refused_for_sessions() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut game_client = sessions_client::Game::new(remoting.clone().with_actor_id(43.into()));
+
+    let result = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let result = service_client
+        .disable_action(ActionsForSession::Move)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let result = game_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+
+    let result = service_client
+        .why_not_allowed(ACTOR_ID.into(), 43.into(), ActionsForSession::Move)
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(result, Some(SessionError::ActionIsDisabled));
+
+    // the session itself is kept and works again once the action is enabled
+    let result = service_client
+        .enable_action(ActionsForSession::Move)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let result = game_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn frozen_program_refuses_new_sessions() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut other_client = sessions_client::Session::new(remoting.clone().with_actor_id(44.into()));
+    let mut game_client = sessions_client::Game::new(remoting.clone().with_actor_id(43.into()));
+
+    let result = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    // only the admin freezes the program
+    let result = other_client.freeze().send_recv(program_id).await;
+
+    assert!(result.is_err());
+
+    let result = service_client.freeze().send_recv(program_id).await;
+
+    assert!(result.is_ok());
+    assert!(service_client.is_frozen().recv(program_id).await.unwrap());
+
+    let result = other_client
+        .create_session(signature_data_for(45, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::SessionsAreFrozen)
+    ));
+
+    // the existing sessions are still usable
+    let result = game_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let result = service_client.unfreeze().send_recv(program_id).await;
+
+    assert!(result.is_ok());
+
+    let result = other_client
+        .create_session(signature_data_for(45, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn action_is_cooling_down_after_use() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut game_client = sessions_client::Game::new(remoting.clone().with_actor_id(43.into()));
+
+    let signature_data = SignatureData {
+        action_cooldowns: vec![(ActionsForSession::Move, 5)],
+        ..signature_data_for(43, vec![ActionsForSession::Move])
+    };
+
+    let result = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let result = game_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), 1);
+
+    let result = service_client
+        .why_not_allowed(ACTOR_ID.into(), 43.into(), ActionsForSession::Move)
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    let Some(SessionError::ActionIsCoolingDown { usable_from_block }) = result else {
+        panic!("unexpected diagnostics: {result:?}");
+    };
+
+    let result = game_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+
+    remoting.system().run_to_block(usable_from_block);
+
+    let result = game_client
+        .make_move(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn session_status_lists_usable_actions() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let status = service_client
+        .gasless_status(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(status.session.is_none());
+    assert!(status.usable_actions.is_empty());
+
+    let signature_data = signature_data_for(
+        43,
+        vec![ActionsForSession::StartGame, ActionsForSession::Move],
+    );
+
+    let (_, created) = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let result = service_client
+        .disable_action(ActionsForSession::Move)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let status = service_client
+        .gasless_status(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(status.session, Some(created));
+    assert_eq!(status.usable_actions, vec![ActionsForSession::StartGame]);
+}
+
+#[tokio::test]
+async fn failed_call_reports_error_code() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let result = service_client
+        .delete_session_from_account()
+        .send_recv(program_id)
+        .await;
+
+    let Err(sails_rs::errors::Error::Rtl(sails_rs::errors::RtlError::ReplyHasError(_, payload))) =
+        result
+    else {
+        panic!("unexpected result: {result:?}");
+    };
+
+    let message = String::from_utf8_lossy(payload.as_ref());
+    assert!(message.contains("9: NoSession"), "{message}");
+}
+
+#[tokio::test]
+async fn service_info_reports_capabilities() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let service_client = sessions_client::Session::new(remoting.clone());
+
+    let info = service_client
+        .service_info()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    // `session-service` shares the workspace version with this crate
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+
+    // the example designates `Skip` as read-only, so spectator sessions are supported,
+    // but neither program vouchers nor multiple sessions per account are
+    let spectator_sessions = 1 << 3;
+    let vouchers = 1 << 8;
+    let multiple_sessions = 1 << 9;
+    assert_ne!(info.capabilities & spectator_sessions, 0);
+    assert_eq!(info.capabilities & (vouchers | multiple_sessions), 0);
+}
+
+#[tokio::test]
+async fn why_not_allowed_explains_refusals() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let result = service_client
+        .why_not_allowed(ACTOR_ID.into(), 43.into(), ActionsForSession::Move)
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(result, Some(SessionError::NoSession));
+
+    let result = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let result = service_client
+        .why_not_allowed(ACTOR_ID.into(), 44.into(), ActionsForSession::Move)
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(result, Some(SessionError::NotSessionKey));
+
+    let result = service_client
+        .why_not_allowed(ACTOR_ID.into(), 43.into(), ActionsForSession::StartGame)
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(result, Some(SessionError::ActionIsNotAllowed));
+
+    let result = service_client
+        .why_not_allowed(ACTOR_ID.into(), 43.into(), ActionsForSession::Move)
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(result, None);
+}
+
+#[tokio::test]
+async fn session_requested_by_key_is_approved_by_owner() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut key_client = sessions_client::Session::new(remoting.clone().with_actor_id(43.into()));
+
+    // the key proposes the session, `key` of the request data is the owner
+    let result = key_client
+        .request_session(signature_data_for(ACTOR_ID, vec![ActionsForSession::Move]))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let requests = service_client
+        .session_requests(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].0, ActorId::from(43));
+    assert_eq!(requests[0].1.allowed_actions, vec![ActionsForSession::Move]);
+
+    // nothing is created until the owner approves
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+
+    // only the owner approves the request
+    let result = key_client
+        .approve_session_request(43.into())
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::NoSessionRequest)
+    ));
+
+    let (account, created) = service_client
+        .approve_session_request(43.into())
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(account, ActorId::from(ACTOR_ID));
+    assert_eq!(created.key, ActorId::from(43));
+
+    let requests = service_client
+        .session_requests(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(requests.is_empty());
+}
+
+#[tokio::test]
+async fn expired_session_is_restored_with_reauth_token() {
+    let (remoting, program_id) = deploy(Config {
+        reauth_window_blocks: 100,
+        ..config()
+    })
+    .await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    // not a whole number of blocks, the token keeps the signed duration anyway
+    let signature_data = SignatureData {
+        duration: 200_000,
+        ..signature_data_for(43, vec![ActionsForSession::Move])
+    };
+
+    let (_, created) = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    remoting.system().run_to_block(created.expires_at_block + 1);
+
+    let token = service_client
+        .reauth_token(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap()
+        .expect("the session has expired cleanly");
+
+    assert_eq!(token.key, ActorId::from(43));
+    assert_eq!(token.duration, 200_000);
+
+    let (_, restored) = service_client
+        .restore_session()
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(restored.key, ActorId::from(43));
+    assert_eq!(restored.duration, 200_000);
+    assert_eq!(restored.allowed_actions, vec![ActionsForSession::Move]);
+
+    // the token is used up
+    let result = service_client
+        .reauth_token(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+}
+
+#[tokio::test]
+async fn session_request_is_validated_without_creating() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let service_client = sessions_client::Session::new(remoting.clone());
+
+    let result = service_client
+        .validate_session_request(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(result, Ok(()));
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+
+    let signature_data = SignatureData {
+        duration: 1_000,
+        ..signature_data_for(43, vec![ActionsForSession::Move])
+    };
+
+    let result = service_client
+        .validate_session_request(signature_data, None)
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        result,
+        Err(SessionError::DurationIsSmall {
+            provided: 1_000,
+            required: 180_000,
+        })
+    );
+
+    let result = service_client
+        .validate_session_request(signature_data_for(43, vec![]), None)
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(result, Err(SessionError::ThereAreNoAllowedMessages));
+}
+
+#[tokio::test]
+async fn key_broadens_session_with_owner_signature() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    // `ACTOR_ID` is the key of the session signed by the owner
+    let pair: Keypair = Keypair::generate_with(OsRng);
+    let owner = ActorId::from(pair.public.to_bytes());
+    let mut signature_data = signature_data_for(ACTOR_ID, vec![ActionsForSession::Move]);
+    signature_data.signature_valid_until = signature_valid_until(&remoting);
+    let raw_signature = sign(&pair, signature_data.clone());
+
+    let signature_data = SignatureData {
+        key: owner,
+        ..signature_data
+    };
+
+    let (_, created) = service_client
+        .create_session(signature_data, Some(raw_signature))
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let result = service_client
+        .update_allowed_actions(owner, vec![ActionsForSession::Skip], vec![], None)
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), Err(SessionError::ActionIsNotAllowed));
+
+    // `ActionsUpdateData` with the key, the generation and the lists
+    let add = vec![ActionsForSession::Skip];
+    let update_signature = sign(
+        &pair,
+        (
+            ActorId::from(ACTOR_ID),
+            created.generation,
+            add.clone(),
+            Vec::<ActionsForSession>::new(),
+        ),
+    );
+
+    let result = service_client
+        .update_allowed_actions(owner, add.clone(), vec![], Some(update_signature.clone()))
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), Ok(()));
+
+    let session = service_client
+        .session_for_the_account(owner)
+        .recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        session.allowed_actions,
+        vec![ActionsForSession::Move, ActionsForSession::Skip]
+    );
+
+    // the key narrows the session freely, but can't replay the signature to broaden it again
+    let result = service_client
+        .update_allowed_actions(owner, vec![], add.clone(), None)
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), Ok(()));
+
+    let result = service_client
+        .update_allowed_actions(owner, add.clone(), vec![], Some(update_signature))
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), Err(SessionError::SignatureAlreadyUsed));
+
+    // the update would be missing from the exported snapshot
+    let result = service_client.freeze().send_recv(program_id).await;
+
+    assert!(result.is_ok());
+
+    let result = service_client
+        .update_allowed_actions(owner, vec![], vec![ActionsForSession::Move], None)
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), Err(SessionError::SessionsAreFrozen));
+}
+
+#[tokio::test]
+async fn deposit_is_topped_up_by_its_depositor() {
+    let (remoting, program_id) = deploy(Config {
+        deposit_per_byte: 1_000_000_000,
+        ..config()
+    })
+    .await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut key_client = sessions_client::Session::new(remoting.clone().with_actor_id(43.into()));
+
+    let result = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .with_value(1_000_000_000_000_000)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    // the deposit is returned to the owner, so the key can't add to it
+    let result = key_client
+        .update_allowed_actions(ACTOR_ID.into(), vec![], vec![], None)
+        .with_value(1_000_000_000_000)
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), Err(SessionError::NotDepositor));
+
+    let result = service_client
+        .update_allowed_actions(ACTOR_ID.into(), vec![ActionsForSession::Skip], vec![], None)
+        .with_value(1_000_000_000_000)
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), Ok(()));
+}
+
+#[tokio::test]
+async fn session_remaining_counts_down() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let (_, created) = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let (ms, blocks) = service_client
+        .session_remaining(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap()
+        .expect("the session is active");
+
+    // 180 seconds are 60 blocks of 3 seconds
+    assert!(blocks > 0 && blocks <= 60);
+    assert_eq!(ms, blocks * 3_000);
+
+    remoting.system().run_to_block(created.expires_at_block);
+
+    let result = service_client
+        .session_remaining(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+}
+
+#[tokio::test]
+async fn group_revocation_ends_its_sessions() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let result = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let result = service_client
+        .set_session_group(ACTOR_ID.into(), "a".repeat(33))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+
+    let result = service_client
+        .set_session_group(ACTOR_ID.into(), "tournament".into())
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let result = service_client
+        .sessions_in_group(ACTOR_ID.into(), "tournament".into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].0, ActorId::from(ACTOR_ID));
+
+    // another group of the owner is left alone
+    let result = service_client
+        .revoke_group("casual".into())
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_some());
+
+    let result = service_client
+        .revoke_group("tournament".into())
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+}
+
+#[tokio::test]
+async fn pending_deletions_are_listed() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let (_, created) = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let pending = service_client
+        .pending_deletions()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].account, ActorId::from(ACTOR_ID));
+    assert_eq!(pending[0].at_block, created.expires_at_block);
+    assert_eq!(pending[0].generation, created.generation);
+    assert!(!pending[0].orphaned);
+
+    let result = service_client
+        .sessions_without_deletion()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_empty());
+
+    // the message is still queued after the session is deleted, it just won't do anything
+    let result = service_client
+        .delete_session_from_account()
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let pending = service_client
+        .pending_deletions()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(pending.len(), 1);
+    assert!(pending[0].orphaned);
+
+    remoting.system().run_to_block(created.expires_at_block + 1);
+
+    let pending = service_client
+        .pending_deletions()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(pending.is_empty());
+}
+
+#[tokio::test]
+async fn creation_replies_with_stored_session() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut other_client = sessions_client::Session::new(remoting.clone().with_actor_id(44.into()));
+
+    let (account, created) = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let stored = service_client
+        .session_for_the_account(account)
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(stored, Some(created));
+
+    let spectator_data = SpectatorData {
+        key: 45.into(),
+        duration: 180_000,
+        signature_valid_until: 0,
+    };
+
+    let (account, created) = other_client
+        .create_spectator_session(spectator_data, None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(account, ActorId::from(44));
+
+    let stored = service_client
+        .session_for_the_account(account)
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(stored, Some(created));
+}
+
+#[tokio::test]
+async fn deletion_gas_follows_measured_baseline() {
+    let (remoting, program_id) = deploy(Config {
+        deletion_gas_margin_percent: 20,
+        ..config()
+    })
+    .await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut other_client = sessions_client::Session::new(remoting.clone().with_actor_id(44.into()));
+
+    // nothing is measured before the first deletion runs
+    let gas = service_client
+        .deletion_gas()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(gas, config().gas_to_delete_session);
+
+    let (_, created) = service_client
+        .create_session(signature_data_for(43, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    remoting.system().run_to_block(created.expires_at_block + 1);
+
+    let measured = service_client
+        .deletion_gas()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(measured > 0);
+    assert!(measured < config().gas_to_delete_session);
+
+    // only the admin recalibrates
+    let result = other_client
+        .recalibrate_deletion_gas(1_000_000_000)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+
+    let result = service_client
+        .recalibrate_deletion_gas(1_000_000_000)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    let gas = service_client
+        .deletion_gas()
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert_eq!(gas, 1_200_000_000);
+}
+
+#[tokio::test]
+async fn session_duration_is_given_in_blocks() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let signature_data = SignatureData {
+        duration_blocks: 60,
+        ..signature_data_for(43, vec![ActionsForSession::Move])
+    };
+
+    let result = service_client
+        .create_session(signature_data.clone(), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::DurationIsAmbiguous)
+    ));
+
+    let signature_data = SignatureData {
+        duration: 0,
+        ..signature_data
+    };
+
+    let (_, created) = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // both forms are kept, derived with `ms_per_block`
+    assert_eq!(created.duration, 180_000);
+    assert_eq!(created.duration_blocks, 60);
+    assert_eq!(created.expires_at_block, created.created_at_block + 60);
+
+    remoting.system().run_to_block(created.expires_at_block + 1);
+
+    let result = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap();
+
+    assert!(result.is_none());
+}
+
+#[tokio::test]
+async fn session_guard_authorizes_action_once_per_message() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+    let mut game_client = sessions_client::Game::new(remoting.clone().with_actor_id(43.into()));
+
+    let signature_data = SignatureData {
+        action_cooldowns: vec![(ActionsForSession::Move, 5)],
+        ..signature_data_for(43, vec![ActionsForSession::Move, ActionsForSession::Skip])
+    };
+
+    let result = service_client
+        .create_session(signature_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    // `Move` is required three times in the message, by the guard and by `get_actor_id`,
+    // the cooldown only counts the message once
+    let result = game_client
+        .move_and_skip(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), 1);
+
+    // the next message is a new use of `Move`
+    let result = game_client
+        .move_and_skip(Some(ACTOR_ID.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+
+    // a session without `Skip` fails on the second check and the move is reverted
+    let mut other_client = sessions_client::Session::new(remoting.clone().with_actor_id(44.into()));
+    let mut other_game_client =
+        sessions_client::Game::new(remoting.clone().with_actor_id(45.into()));
+
+    let result = other_client
+        .create_session(signature_data_for(45, vec![ActionsForSession::Move]), None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let result = other_game_client
+        .move_and_skip(Some(44.into()))
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_err());
+
+    let result = game_client.moves(44.into()).recv(program_id).await.unwrap();
+
+    assert_eq!(result, 0);
+
+    // the owner itself is allowed every action
+    let mut owner_game_client = sessions_client::Game::new(remoting.clone());
+
+    let result = owner_game_client
+        .move_and_skip(None)
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn signature_is_accepted_only_within_its_validity() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let pair: Keypair = Keypair::generate_with(OsRng);
+    let owner = ActorId::from(pair.public.to_bytes());
+    let now = remoting.system().block_timestamp();
+
+    // the validity can't be set further than `MAX_SIGNATURE_VALIDITY_MS` ahead
+    let mut signature_data = signature_data_for(ACTOR_ID, vec![ActionsForSession::StartGame]);
+    signature_data.signature_valid_until = now + 86_400_000 + 3_000;
+    let raw_signature = sign(&pair, signature_data.clone());
+
+    let result = service_client
+        .create_session(
+            SignatureData {
+                key: owner,
+                ..signature_data
+            },
+            Some(raw_signature),
+        )
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::SignatureValidityIsTooLong)
+    ));
+
+    // the signature is valid for 100 blocks, longer than the session lasts
+    let mut signature_data = signature_data_for(ACTOR_ID, vec![ActionsForSession::StartGame]);
+    signature_data.signature_valid_until = now + 300_000;
+    let raw_signature = sign(&pair, signature_data.clone());
+    let signature_data = SignatureData {
+        key: owner,
+        ..signature_data
+    };
+
+    let (_, session) = service_client
+        .create_session(signature_data.clone(), Some(raw_signature.clone()))
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    remoting.system().run_to_block(session.expires_at_block);
+
+    let result = service_client
+        .create_session(signature_data.clone(), Some(raw_signature.clone()))
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::SignatureAlreadyUsed)
+    ));
+
+    // past the validity the signature is refused by its validity alone
+    // and no longer needs to be remembered
+    remoting
+        .system()
+        .run_to_block(session.created_at_block + 101);
+
+    let result = service_client
+        .create_session(signature_data, Some(raw_signature))
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::SignatureExpired)
+    ));
+}
+
+#[tokio::test]
+async fn owners_are_checked_by_eligibility_registry() {
+    let remoting = GTestRemoting::new(ACTOR_ID.into());
+    remoting.system().init_logger();
+
+    // `ACTOR_ID` is eligible, `43` isn't and the registry panics on any other account
+    let registry_code_id = remoting
+        .system()
+        .submit_code(eligibility_registry::WASM_BINARY);
+    let (registry_id, _) = remoting
+        .clone()
+        .activate(
+            registry_code_id,
+            b"salt",
+            vec![(ActorId::from(ACTOR_ID), true), (ActorId::from(43), false)].encode(),
+            None,
+            0,
+            GTestArgs::default(),
+        )
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+
+    let program_code_id = remoting.system().submit_code(sessions::WASM_BINARY);
+    let program_id = sessions_client::SessionsFactory::new(remoting.clone())
+        .new(Config {
+            eligibility_registry: Some(registry_id),
+            max_attempts_per_block: 1,
+            ..config()
+        })
+        .send_recv(program_code_id, b"salt")
+        .await
+        .unwrap();
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let result = service_client
+        .create_session(
+            signature_data_for(10, vec![ActionsForSession::StartGame]),
+            None,
+        )
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    // the value attached to a refused creation is sent back
+    let balance = remoting.system().balance_of(program_id);
+
+    let mut ineligible_client =
+        sessions_client::Session::new(remoting.clone().with_actor_id(43.into()));
+
+    let result = ineligible_client
+        .create_session(
+            signature_data_for(11, vec![ActionsForSession::StartGame]),
+            None,
+        )
+        .with_value(1_000_000_000_000)
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(result.unwrap(), Err(SessionError::NotEligible)));
+    assert_eq!(remoting.system().balance_of(program_id), balance);
+
+    // the registry panics on `44`
+    let mut unknown_client =
+        sessions_client::Session::new(remoting.clone().with_actor_id(44.into()));
+
+    let result = unknown_client
+        .create_session(
+            signature_data_for(11, vec![ActionsForSession::StartGame]),
+            None,
+        )
+        .with_value(1_000_000_000_000)
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::EligibilityCheckFailed)
+    ));
+    assert_eq!(remoting.system().balance_of(program_id), balance);
+
+    // the refused creation is still counted as an attempt
+    let remoting = remoting
+        .with_actor_id(43.into())
+        .with_block_run_mode(BlockRunMode::Manual);
+    let mut ineligible_client = sessions_client::Session::new(remoting.clone());
+
+    let first = ineligible_client
+        .create_session(
+            signature_data_for(11, vec![ActionsForSession::StartGame]),
+            None,
+        )
+        .send(program_id)
+        .await
+        .unwrap();
+
+    let second = ineligible_client
+        .request_session(signature_data_for(
+            ACTOR_ID,
+            vec![ActionsForSession::StartGame],
+        ))
+        .send(program_id)
+        .await
+        .unwrap();
+
+    remoting.run_next_block();
+
+    assert!(matches!(
+        first.recv().await.unwrap(),
+        Err(SessionError::NotEligible)
+    ));
+    assert!(matches!(
+        second.recv().await.unwrap(),
+        Err(SessionError::TooManyAttempts { limit: 1 })
+    ));
+}
+
+// Adds the order of the group to the scalar of the signature, which verifies the same
+// unless the scalar is required to be reduced
+fn add_group_order(signature: &mut [u8]) {
+    const ORDER: [u8; 32] = [
+        0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde,
+        0x14, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+    ];
+    // the top bit of the scalar marks schnorrkel signatures
+    signature[63] &= 0x7f;
+    let mut carry = 0;
+    for (byte, order_byte) in signature[32..].iter_mut().zip(ORDER) {
+        let sum = *byte as u16 + order_byte as u16 + carry;
+        *byte = sum as u8;
+        carry = sum >> 8;
+    }
+    signature[63] |= 0x80;
+}
+
+#[tokio::test]
+async fn malleated_signature_is_rejected() {
+    let (remoting, program_id) = deploy(config()).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    let pair: Keypair = Keypair::generate_with(OsRng);
+    let mut signature_data = signature_data_for(ACTOR_ID, vec![ActionsForSession::StartGame]);
+    signature_data.signature_valid_until = signature_valid_until(&remoting);
+    let raw_signature = sign(&pair, signature_data.clone());
+    let signature_data = SignatureData {
+        key: pair.public.to_bytes().into(),
+        ..signature_data
+    };
+
+    // other encodings of the same signature would be remembered under other hashes
+    let mut unreduced_scalar = raw_signature.clone();
+    add_group_order(&mut unreduced_scalar);
+    let mut unmarked = raw_signature.clone();
+    unmarked[63] &= 0x7f;
+    let mut invalid_point = raw_signature.clone();
+    invalid_point[..32].copy_from_slice(&[0xff; 32]);
+
+    for malleated in [&unreduced_scalar, &unmarked, &invalid_point] {
+        let result = service_client
+            .create_session(signature_data.clone(), Some(malleated.clone()))
+            .send_recv(program_id)
+            .await;
+
+        assert!(matches!(result.unwrap(), Err(SessionError::BadSignature)));
+    }
+
+    let (_, session) = service_client
+        .create_session(signature_data.clone(), Some(raw_signature.clone()))
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    remoting.system().run_to_block(session.expires_at_block);
+
+    let result = service_client
+        .create_session(signature_data.clone(), Some(raw_signature))
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(
+        result.unwrap(),
+        Err(SessionError::SignatureAlreadyUsed)
+    ));
+
+    // and the other encodings can't get around it
+    let result = service_client
+        .create_session(signature_data, Some(unreduced_scalar))
+        .send_recv(program_id)
+        .await;
+
+    assert!(matches!(result.unwrap(), Err(SessionError::BadSignature)));
+}
+
+// Calls a method of `sessions-routed`, whose service the generated client can't reach
+// as it's exposed under the `Sessions` route
+async fn call_sessions_route<T: Decode>(
+    remoting: &GTestRemoting,
+    program_id: ActorId,
+    method: &str,
+    args: impl Encode,
+) -> T {
+    let reply = remoting
+        .clone()
+        .message(
+            program_id,
+            ("Sessions", method, args).encode(),
+            None,
+            0,
+            GTestArgs::default(),
+        )
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    let (_, _, result) = <(String, String, T)>::decode(&mut reply.as_slice()).unwrap();
+    result
+}
+
+#[tokio::test]
+async fn session_is_deleted_under_custom_service_route() {
+    let remoting = GTestRemoting::new(ACTOR_ID.into());
+    remoting.system().init_logger();
+
+    // the constructor is the same, so the factory of `sessions` deploys it as well
+    let program_code_id = remoting.system().submit_code(sessions_routed::WASM_BINARY);
+    let program_id = sessions_client::SessionsFactory::new(remoting.clone())
+        .new(config())
+        .send_recv(program_code_id, b"salt")
+        .await
+        .unwrap();
+
+    let signature_data = signature_data_for(10, vec![ActionsForSession::StartGame]);
+    let result: Result<(ActorId, SessionData), SessionError> = call_sessions_route(
+        &remoting,
+        program_id,
+        "CreateSession",
+        (signature_data, None::<Vec<u8>>),
+    )
+    .await;
+    let (_, session) = result.unwrap();
+
+    // the scheduled deletion is sent to `Sessions` and executed there
+    let block_results = remoting.system().run_to_block(session.expires_at_block);
+
+    let session_deleted = ["Sessions".encode(), "SessionDeleted".encode()].concat();
+    let event_emitted = block_results
+        .iter()
+        .flat_map(|block_result| block_result.log())
+        .any(|log| log.source() == program_id && log.payload().starts_with(&session_deleted));
+
+    assert!(event_emitted);
+
+    let result: Option<SessionData> = call_sessions_route(
+        &remoting,
+        program_id,
+        "SessionForTheAccount",
+        ActorId::from(ACTOR_ID),
+    )
+    .await;
+
+    assert!(result.is_none());
+}
+
+#[tokio::test]
+async fn owner_broadens_session_with_consent_of_key() {
+    let config = Config {
+        require_key_signature: true,
+        ..config()
+    };
+    let (remoting, program_id) = deploy(config).await;
+
+    let mut service_client = sessions_client::Session::new(remoting.clone());
+
+    // a spectator session stays limited to the read-only actions
+    let spectator_data = SpectatorData {
+        key: 43.into(),
+        duration: 180_000,
+        signature_valid_until: 0,
+    };
+
+    let result = service_client
+        .create_spectator_session(spectator_data, None)
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.unwrap().is_ok());
+
+    let result = service_client
+        .update_allowed_actions(ACTOR_ID.into(), vec![ActionsForSession::Move], vec![], None)
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), Err(SessionError::ActionIsNotAllowed));
+
+    let result = service_client
+        .delete_session_from_account()
+        .send_recv(program_id)
+        .await;
+
+    assert!(result.is_ok());
+
+    // the key consents to the session and then to broadening it
+    let pair: Keypair = Keypair::generate_with(OsRng);
+    let key = ActorId::from(pair.public.to_bytes());
+    let mut signature_data = signature_data_for(ACTOR_ID, vec![ActionsForSession::Move]);
+    signature_data.signature_valid_until = signature_valid_until(&remoting);
+    let key_signature = sign(&pair, signature_data.clone());
+
+    let (_, created) = service_client
+        .create_session_signed_by_key(
+            SignatureData {
+                key,
+                ..signature_data
+            },
+            key_signature,
+        )
+        .send_recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let add = vec![ActionsForSession::Skip];
+
+    let result = service_client
+        .update_allowed_actions(ACTOR_ID.into(), add.clone(), vec![], None)
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), Err(SessionError::KeySignatureRequired));
+
+    // `ActionsUpdateData` with the owner in place of the key
+    let update_signature = sign(
+        &pair,
+        (
+            ActorId::from(ACTOR_ID),
+            created.generation,
+            add.clone(),
+            Vec::<ActionsForSession>::new(),
+        ),
+    );
+
+    let result = service_client
+        .update_allowed_actions(ACTOR_ID.into(), add, vec![], Some(update_signature))
+        .send_recv(program_id)
+        .await;
+
+    assert_eq!(result.unwrap(), Ok(()));
+
+    let session = service_client
+        .session_for_the_account(ACTOR_ID.into())
+        .recv(program_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        session.allowed_actions,
+        vec![ActionsForSession::Move, ActionsForSession::Skip]
+    );
+}
+
+#[tokio::test]
+async fn init_with_invalid_config_fails() {
+    let remoting = GTestRemoting::new(ACTOR_ID.into());
+    remoting.system().init_logger();
+
+    // Submit program code into the system
+    let program_code_id = remoting.system().submit_code(sessions::WASM_BINARY);
+
+    let program_factory = sessions_client::SessionsFactory::new(remoting.clone());
+
+    let config = Config {
+        ms_per_block: 0,
+        ..config()
+    };
+
+    let result = program_factory
+        .new(config)
+        .send_recv(program_code_id, b"salt")
+        .await;
+
+    assert!(result.is_err());
+
+    // sliding sessions without a maximum lifetime
+    let config = Config {
+        sliding_expiration_ms: 600_000,
+        ..config()
+    };
+
+    let result = program_factory
+        .new(config)
+        .send_recv(program_code_id, b"sliding salt")
+        .await;
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn signature_data_accepts_addresses() {
+    let public_key = "0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+    let ss58 = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY";
+    let actions = vec![ActionsForSession::StartGame];
+
+    let from_hex = SignatureData::new(public_key, 180_000, actions.clone(), Some(42)).unwrap();
+    let from_ss58 = SignatureData::new(ss58, 180_000, actions.clone(), Some(42)).unwrap();
+    let from_id = SignatureData::new(from_hex.key, 180_000, actions.clone(), Some(42)).unwrap();
+
+    assert_eq!(from_hex.key, from_ss58.key);
+    assert_eq!(from_hex.key, from_id.key);
+
+    // the address is encoded for another network
+    let result = SignatureData::new(ss58, 180_000, actions, Some(137));
+    assert!(matches!(
+        result,
+        Err(AddressError::WrongNetwork {
+            expected: 137,
+            found: 42
+        })
+    ));
+}
+
+#[test]
+fn malformed_addresses_are_rejected() {
+    let public_key = "0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+    let account = parse_address(public_key, None).unwrap();
+
+    // the two-byte prefix of the Vara network
+    let vara = to_ss58(account, VARA_SS58_PREFIX);
+    assert_eq!(parse_address(&vara, Some(VARA_SS58_PREFIX)), Ok(account));
+    assert_eq!(
+        parse_address(&vara, Some(SUBSTRATE_SS58_PREFIX)),
+        Err(AddressError::WrongNetwork {
+            expected: SUBSTRATE_SS58_PREFIX,
+            found: VARA_SS58_PREFIX
+        })
+    );
+
+    // the last character is a part of the checksum
+    let (body, last) = vara.split_at(vara.len() - 1);
+    let replacement = if last == "1" { "2" } else { "1" };
+    assert_eq!(
+        parse_address(&[body, replacement].concat(), None),
+        Err(AddressError::InvalidChecksum)
+    );
+
+    assert_eq!(parse_address(body, None), Err(AddressError::InvalidFormat));
+
+    // a sign is not a hex digit
+    let signed = public_key.replacen("0xd4", "0x+4", 1);
+    assert_eq!(
+        parse_address(&signed, None),
+        Err(AddressError::InvalidFormat)
+    );
 }
@@ -0,0 +1,19 @@
+// Bits of `ServiceInfo::capabilities`. A bit keeps its meaning across releases,
+// new capabilities take the next free one
+
+// Owners can sign the delegation off-chain with an sr25519 key
+pub const SR25519_SIGNATURES: u64 = 1 << 0;
+// Session keys can sign their consent, see `Config::require_key_signature`
+pub const KEY_SIGNATURES: u64 = 1 << 1;
+// One session for several owners signing together
+pub const TEAM_SESSIONS: u64 = 1 << 2;
+// Set only if the program designated read-only actions
+pub const SPECTATOR_SESSIONS: u64 = 1 << 3;
+pub const TEMPLATES: u64 = 1 << 4;
+pub const TRUSTED_FORWARDERS: u64 = 1 << 5;
+pub const SLIDING_EXPIRATION: u64 = 1 << 6;
+pub const ACTION_COOLDOWNS: u64 = 1 << 7;
+// Not supported yet: vouchers issued by the program for its sessions
+pub const VOUCHERS: u64 = 1 << 8;
+// Not supported yet: more than one session per account
+pub const MULTIPLE_SESSIONS: u64 = 1 << 9;
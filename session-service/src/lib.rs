@@ -2,5 +2,9 @@
 pub use gstd::{exec, msg};
 pub use schnorrkel::{PublicKey, Signature};
 
+pub mod capabilities;
 mod macros;
 pub mod utils;
+
+// Version of this crate the session service is generated with
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
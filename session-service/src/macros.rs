@@ -30,25 +30,375 @@
 /// }
 ///
 /// generate_session_system!(ActionsForSession);
+/// ```
+///
+/// The service is expected under the `Session` route, i.e. returned by a program method named
+/// `session`. Otherwise the route must be passed by hand, since the macro can't see the program:
+/// ```rust, ignore
+/// generate_session_system!(ActionsForSession, service_route: "Sessions");
+/// ```
+///
+/// Actions that don't change the state of the program can be designated for spectator sessions:
+/// ```rust, ignore
+/// generate_session_system!(ActionsForSession, read_only: [ActionsForSession::Skip]);
+/// ```
 #[macro_export]
 macro_rules! generate_session_system {
     ($actions_enum:ident) => {
+        $crate::generate_session_system!($actions_enum, read_only: [], service_route: "Session");
+    };
+    ($actions_enum:ident, service_route: $service_route:literal) => {
+        $crate::generate_session_system!(
+            $actions_enum,
+            read_only: [],
+            service_route: $service_route
+        );
+    };
+    ($actions_enum:ident, read_only: [$($read_only:expr),* $(,)?]) => {
+        $crate::generate_session_system!(
+            $actions_enum,
+            read_only: [$($read_only),*],
+            service_route: "Session"
+        );
+    };
+    (
+        $actions_enum:ident,
+        read_only: [$($read_only:expr),* $(,)?],
+        service_route: $service_route:literal
+    ) => {
         use crate::{exec, msg, PublicKey};
         use sails_rs::fmt::Debug;
-        use sails_rs::{collections::HashMap, gstd::service};
-        use utils::panicking;
+        use sails_rs::{
+            collections::{HashMap, HashSet},
+            gstd::service,
+        };
+        use utils::{canonical_signature, hash};
+
+        // Panics with the code of the error in front of its name, e.g. `9: NoSession`
+        fn panicking<T, F: FnOnce() -> Result<T, SessionError>>(f: F) -> T {
+            f().unwrap_or_else(|error| utils::panic(format_args!("{}: {error:?}", error.code())))
+        }
 
         #[derive(Default)]
-        pub struct Storage(());
+        pub struct Storage {
+            pub sessions: SessionMap,
+            // The account each session key acts for, used to keep session keys unique
+            pub keys: HashMap<ActorId, ActorId>,
+            pub config: Config,
+            // The generation assigned to the most recently created session
+            pub last_generation: u64,
+            // Hashes of the accepted signatures and the time until they are remembered,
+            // the end of their validity for the ones that created a session
+            pub consumed_signatures: HashMap<[u8; 32], u64>,
+            // Session creation attempts made by each account in `attempts_block`
+            pub attempts: HashMap<ActorId, u32>,
+            pub attempts_block: u32,
+            // Value locked by the creator of each session, returned on its deletion
+            pub deposits: HashMap<ActorId, Deposit>,
+            // Delayed deletions sent for each account that haven't been executed yet
+            pub scheduled_deletions: HashMap<ActorId, Vec<ScheduledDeletion>>,
+            // The initializer of the program, allowed to invalidate scopes
+            pub admin: ActorId,
+            // Scopes no session can be created for anymore
+            pub invalidated_scopes: HashSet<u64>,
+            // Members of each team holding a session, by the id of the team
+            pub teams: HashMap<ActorId, Vec<ActorId>>,
+            // Published templates never change, a signature always refers to the same policy
+            pub templates: HashMap<u32, SessionTemplate>,
+            pub last_template_id: u32,
+            // Programs trusted to pass the original sender of the calls they forward
+            pub trusted_forwarders: HashSet<ActorId>,
+            // Labels the owners gave to their sessions, by the account of the session
+            pub labels: HashMap<ActorId, String>,
+            // Sessions the owners were notified about the first use of
+            pub used_sessions: HashSet<ActorId>,
+            // Sessions created with `create_spectator_session`, which the key never consented
+            // to anything beyond the read-only actions for
+            pub spectator_sessions: HashSet<ActorId>,
+            // Blocks a session has to be active for before it can be used for the action
+            pub action_timelocks: Vec<($actions_enum, u32)>,
+            // Actions no session can be used for until they are enabled again
+            pub disabled_actions: Vec<$actions_enum>,
+            // Block of the last use of each action with a cooldown, by the account of the session
+            pub last_action_uses: HashMap<ActorId, Vec<($actions_enum, u32)>>,
+            // Value the session keys put at stake for the sessions they serve
+            pub stakes: HashMap<ActorId, Stake>,
+            // Allowed to slash stakes besides the admin
+            pub arbiter: Option<ActorId>,
+            // Track record of each key across all the sessions it has served
+            pub key_reputation: HashMap<ActorId, KeyReputation>,
+            // Keys each owner has authorized, the least recently seen ones are dropped first
+            pub delegation_history: HashMap<ActorId, Vec<DelegationRecord>>,
+            // Keys each owner doesn't want to authorize anymore
+            pub blacklisted_keys: HashMap<ActorId, HashSet<ActorId>>,
+            // Sessions created and deleted on expiry since the last `SessionsHeartbeat`
+            pub created_since_heartbeat: u32,
+            pub expired_since_heartbeat: u32,
+            pub last_heartbeat_block: u32,
+            // No session can be created or extended while the state is being migrated
+            pub frozen: bool,
+            // Sessions asked for by the keys and waiting for their owners, by the key
+            pub session_requests: HashMap<ActorId, SessionRequest>,
+            // Sessions that expired cleanly and can be restored by their owners
+            pub reauth_tokens: HashMap<ActorId, ReauthToken>,
+            // Group each owner has put its sessions in, by the owner and the account of the session
+            pub session_groups: HashMap<ActorId, Vec<(ActorId, String)>>,
+            // The most gas an executed deletion has been measured to use
+            pub deletion_gas_baseline: u64,
+        }
+
+        #[derive(Debug, Clone, Copy)]
+        pub struct ScheduledDeletion {
+            // The generation the message carries
+            pub generation: u64,
+            // The session the message deletes, a later one than `generation`
+            // once the deletion has been adopted
+            pub session_generation: u64,
+            pub at_block: u32,
+            // Gas the delayed message was sent with
+            pub gas: u64,
+        }
+
+        pub struct Deposit {
+            pub depositor: ActorId,
+            pub amount: u128,
+        }
+
+        // Numbers of active sessions by the bucket of their duration and of their remaining lifetime.
+        // Bucket `i` holds the values up to `bounds_ms[i]`, the last one holds everything longer
+        #[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct DurationHistogram {
+            pub bounds_ms: Vec<u64>,
+            pub durations: Vec<u32>,
+            pub remaining: Vec<u32>,
+        }
+
+        #[derive(Debug, Clone, Copy, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct DelegationRecord {
+            pub key: ActorId,
+            pub first_seen_block: u32,
+            // The last block a session was created with the key
+            pub last_seen_block: u32,
+        }
+
+        #[derive(Debug, Default, Clone, Copy, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct KeyReputation {
+            pub sessions: u32,
+            // Sessions deleted by their owners before they expired
+            pub revocations: u32,
+            pub slashes: u32,
+        }
+
+        // Lets frontends feature-detect deployments built with different versions of the crate
+        #[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct ServiceInfo {
+            // Semantic version of `session-service`
+            pub version: String,
+            // Bits from `session_service::capabilities`
+            pub capabilities: u64,
+        }
+
+        // Proposed by the session key, the session is created once the owner approves it
+        #[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct SessionRequest {
+            pub owner: ActorId,
+            pub duration: u64,
+            pub allowed_actions: Vec<$actions_enum>,
+            pub scope_id: Option<u64>,
+            pub action_cooldowns: Vec<($actions_enum, u32)>,
+            // The last block the owner can approve the request in
+            pub valid_until_block: u32,
+        }
+
+        // Left by a session that expired cleanly, so that the owner can restore it
+        // for the same key and actions with a single call
+        #[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct ReauthToken {
+            pub key: ActorId,
+            pub duration: u64,
+            pub allowed_actions: Vec<$actions_enum>,
+            pub scope_id: Option<u64>,
+            pub action_cooldowns: Vec<($actions_enum, u32)>,
+            pub valid_until_block: u32,
+        }
+
+        #[derive(Debug, Clone, Copy, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct PendingDeletion {
+            pub account: ActorId,
+            pub at_block: u32,
+            // The generation of the session the deletion is for
+            pub generation: u64,
+            // The session it was scheduled for is gone, the message will do nothing
+            // unless a later session of the account takes it over
+            pub orphaned: bool,
+        }
+
+        // The session of an account and the actions it can be used for right now, the part of
+        // the gasless status a program can tell. Voucher ids, balances and sponsorship budgets
+        // are kept by the runtime and can't be read by a program
+        #[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct GaslessStatus {
+            pub session: Option<SessionData>,
+            // Allowed actions that aren't disabled, none if the session has expired
+            pub usable_actions: Vec<$actions_enum>,
+        }
+
+        #[derive(Debug, Clone, Copy, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct Stake {
+            pub staker: ActorId,
+            pub amount: u128,
+        }
 
         impl Storage {
             pub fn get_session_map() -> &'static SessionMap {
+                &Self::get().sessions
+            }
+
+            pub fn get() -> &'static Self {
                 unsafe { STORAGE.as_ref().expect("Storage is not initialized") }
             }
+
+            pub fn get_mut() -> &'static mut Self {
+                unsafe { STORAGE.as_mut().expect("Storage is not initialized") }
+            }
+
+            pub fn insert_session(&mut self, account: ActorId, session: SessionData) {
+                self.remove_session(&account);
+                let reputation = self.key_reputation.entry(session.key).or_default();
+                reputation.sessions = reputation.sessions.saturating_add(1);
+                self.reauth_tokens.remove(&account);
+                self.created_since_heartbeat = self.created_since_heartbeat.saturating_add(1);
+                self.record_delegation(account, session.key);
+                self.keys.insert(session.key, account);
+                self.sessions.insert(account, session);
+            }
+
+            fn record_delegation(&mut self, owner: ActorId, key: ActorId) {
+                let block_height = exec::block_height();
+                let history = self.delegation_history.entry(owner).or_default();
+                if let Some(record) = history.iter_mut().find(|record| record.key == key) {
+                    record.last_seen_block = block_height;
+                    return;
+                }
+                if history.len() >= MAX_DELEGATION_HISTORY {
+                    if let Some(oldest) = history
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, record)| record.last_seen_block)
+                        .map(|(index, _)| index)
+                    {
+                        history.remove(oldest);
+                    }
+                }
+                history.push(DelegationRecord {
+                    key,
+                    first_seen_block: block_height,
+                    last_seen_block: block_height,
+                });
+            }
+
+            // A session extended on activity has more deletions, the earliest one fires first
+            pub fn scheduled_deletion(
+                &self,
+                account: &ActorId,
+                generation: u64,
+            ) -> Option<ScheduledDeletion> {
+                self.scheduled_deletions
+                    .get(account)?
+                    .iter()
+                    .filter(|deletion| deletion.generation == generation)
+                    .min_by_key(|deletion| deletion.at_block)
+                    .copied()
+            }
+
+            pub fn remove_scheduled_deletion(
+                &mut self,
+                account: &ActorId,
+                generation: u64,
+            ) -> Option<ScheduledDeletion> {
+                let deletions = self.scheduled_deletions.get_mut(account)?;
+                let removed = deletions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, deletion)| deletion.generation == generation)
+                    .min_by_key(|(_, deletion)| deletion.at_block)
+                    .map(|(index, _)| index)
+                    .map(|index| deletions.remove(index));
+                if deletions.is_empty() {
+                    self.scheduled_deletions.remove(account);
+                }
+                removed
+            }
+
+            // Removal of a session by its owner, counted against the key if it's still valid
+            pub fn revoke_session(&mut self, account: &ActorId) -> Option<SessionData> {
+                let session = self.remove_session(account)?;
+                if !session.is_expired() {
+                    let reputation = self.key_reputation.entry(session.key).or_default();
+                    reputation.revocations = reputation.revocations.saturating_add(1);
+                }
+                Some(session)
+            }
+
+            // Every removal returns the deposit of the session, whatever the reason is
+            pub fn remove_session(&mut self, account: &ActorId) -> Option<SessionData> {
+                let session = self.sessions.remove(account)?;
+                if self.keys.get(&session.key) == Some(account) {
+                    self.keys.remove(&session.key);
+                }
+                if let Some(deposit) = self.deposits.remove(account) {
+                    refund(deposit.depositor, deposit.amount);
+                }
+                // A stake that wasn't slashed goes back to the key
+                if let Some(stake) = self.stakes.remove(account) {
+                    refund(stake.staker, stake.amount);
+                }
+                let members = self.teams.remove(account).unwrap_or_default();
+                for owner in members.iter().chain([account]) {
+                    if let Some(groups) = self.session_groups.get_mut(owner) {
+                        groups.retain(|(session_for_account, _)| session_for_account != account);
+                        if groups.is_empty() {
+                            self.session_groups.remove(owner);
+                        }
+                    }
+                }
+                self.labels.remove(account);
+                self.used_sessions.remove(account);
+                self.spectator_sessions.remove(account);
+                self.last_action_uses.remove(account);
+                Some(session)
+            }
+        }
+
+        static mut STORAGE: Option<Storage> = None;
+
+        // Actions authorized with a session in the message being handled,
+        // see `SessionGuard` and `get_actor_id`
+        struct GuardCache {
+            message_id: MessageId,
+            owner: ActorId,
+            actions: Vec<$actions_enum>,
         }
 
-        static mut STORAGE: Option<SessionMap> = None;
-        static mut CONFIG: Option<Config> = None;
+        static mut GUARD_CACHE: Option<GuardCache> = None;
 
         #[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
         #[codec(crate = sails_rs::scale_codec)]
@@ -56,6 +406,82 @@ macro_rules! generate_session_system {
         pub enum Event {
             SessionCreated,
             SessionDeleted,
+            ScopeInvalidated {
+                scope_id: u64,
+                deleted_sessions: u32,
+            },
+            TemplatePublished {
+                template_id: u32,
+            },
+            TemplateRetired {
+                template_id: u32,
+            },
+            TrustedForwarderAdded {
+                forwarder: ActorId,
+            },
+            TrustedForwarderRemoved {
+                forwarder: ActorId,
+            },
+            SessionsImported {
+                from: ActorId,
+                imported: u32,
+            },
+            ActionTimelockSet {
+                action: $actions_enum,
+                blocks: u32,
+            },
+            SessionsFrozen,
+            SessionsUnfrozen,
+            SessionRequested {
+                owner: ActorId,
+                key: ActorId,
+            },
+            DeletionGasRecalibrated {
+                baseline: u64,
+            },
+            GroupRevoked {
+                owner: ActorId,
+                name: String,
+                deleted_sessions: u32,
+            },
+            AllowedActionsUpdated {
+                session_for_account: ActorId,
+                allowed_actions: Vec<$actions_enum>,
+            },
+            ActionDisabled {
+                action: $actions_enum,
+            },
+            ActionEnabled {
+                action: $actions_enum,
+            },
+            SessionStaked {
+                session_for_account: ActorId,
+                amount: u128,
+            },
+            StakeSlashed {
+                session_for_account: ActorId,
+                amount: u128,
+            },
+            // `active` counts the sessions not deleted yet, including the expired ones
+            // whose deletion hasn't been executed
+            SessionsHeartbeat {
+                active: u32,
+                created_since_last: u32,
+                expired_since_last: u32,
+            },
+        }
+
+        // Sent to the owner of a session, so that wallets show the activity without an indexer
+        #[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub enum SessionNotification {
+            FirstUse { key: ActorId },
+            // The key asks for a session, see `approve_session_request`
+            SessionRequested { key: ActorId },
+            // The session is extended on activity from now on, but not past `until`
+            Extended { until: u64 },
+            Expired,
         }
 
         #[derive(Clone)]
@@ -63,23 +489,72 @@ macro_rules! generate_session_system {
 
         impl SessionService {
             pub fn init(config: Config) -> Self {
+                panicking(|| config.validate());
                 unsafe {
-                    STORAGE = Some(HashMap::new());
-                    CONFIG = Some(config);
+                    STORAGE = Some(Storage {
+                        config,
+                        admin: msg::source(),
+                        last_heartbeat_block: exec::block_height(),
+                        ..Default::default()
+                    });
                 }
                 Self(())
             }
 
+            pub fn storage_mut(&mut self) -> &'static mut Storage {
+                Storage::get_mut()
+            }
+
+            pub fn storage(&self) -> &'static Storage {
+                Storage::get()
+            }
+
             pub fn as_mut(&mut self) -> &'static mut SessionMap {
-                unsafe { STORAGE.as_mut().expect("Storage is not initialized") }
+                &mut Storage::get_mut().sessions
             }
 
             pub fn as_ref(&self) -> &'static SessionMap {
-                unsafe { STORAGE.as_ref().expect("Storage is not initialized") }
+                &Storage::get().sessions
             }
 
             pub fn config(&self) -> &'static Config {
-                unsafe { CONFIG.as_ref().expect("Config is not initialized") }
+                &Storage::get().config
+            }
+
+            // For the other services of the program: the account the sender acts for,
+            // panics if the sender isn't allowed to send `action` on its behalf
+            pub fn get_actor_id(
+                &mut self,
+                session_for_account: Option<ActorId>,
+                action: $actions_enum,
+            ) -> ActorId {
+                let storage = self.storage_mut();
+                panicking(|| get_actor_id(storage, session_for_account, &action))
+            }
+
+            // The same for a call forwarded by a trusted program on behalf of `original_sender`,
+            // which has to be the key of the session of `session_for_account`
+            pub fn get_forwarded_actor_id(
+                &mut self,
+                original_sender: ActorId,
+                session_for_account: ActorId,
+                action: $actions_enum,
+            ) -> ActorId {
+                let storage = self.storage_mut();
+                panicking(|| {
+                    get_forwarded_actor_id(
+                        storage,
+                        original_sender,
+                        session_for_account,
+                        &action,
+                    )
+                })
+            }
+
+            // Events are best-effort: the state change they describe is already applied
+            // and must not be reverted because the notification couldn't be sent
+            fn emit(&mut self, event: Event) {
+                let _ = self.notify_on(event);
             }
         }
 
@@ -89,221 +564,2693 @@ macro_rules! generate_session_system {
                 Self(())
             }
 
-            pub fn create_session(
+            // Errors are returned rather than panicked with,
+            // so that failed attempts are still counted by the rate limit.
+            // Replies with the stored session and the account it's stored for
+            pub async fn create_session(
                 &mut self,
                 signature_data: SignatureData,
                 signature: Option<Vec<u8>>,
-            ) {
-                let sessions = self.as_mut();
-                let config = self.config();
-                let event =
-                    panicking(|| create_session(sessions, config, signature_data, signature));
-                self.notify_on(event).expect("Notification Error");
+            ) -> Result<(ActorId, SessionData), SessionError> {
+                let owner = match signature {
+                    Some(_) => signature_data.key,
+                    None => msg::source(),
+                };
+                let created = admit(&[owner])
+                    .await
+                    .and_then(|()| create_session(self.storage_mut(), signature_data, signature))
+                    .inspect_err(|_| {
+                        // The message doesn't fail, so the attached value has to be sent back
+                        refund(msg::source(), msg::value());
+                    })?;
+                self.emit(Event::SessionCreated);
+                Ok(created)
             }
 
-            pub fn delete_session_from_program(&mut self, session_for_account: ActorId) {
-                let sessions = self.as_mut();
-                let event =
-                    panicking(|| delete_session_from_program(sessions, session_for_account));
-                self.notify_on(event).expect("Notification Error");
+            // Self path carrying the consent of the session key, see `Config::require_key_signature`
+            pub async fn create_session_signed_by_key(
+                &mut self,
+                signature_data: SignatureData,
+                key_signature: Vec<u8>,
+            ) -> Result<(ActorId, SessionData), SessionError> {
+                let created = admit(&[msg::source()])
+                    .await
+                    .and_then(|()| {
+                        create_session_signed_by_key(
+                            self.storage_mut(),
+                            signature_data,
+                            key_signature,
+                        )
+                    })
+                    .inspect_err(|_| refund(msg::source(), msg::value()))?;
+                self.emit(Event::SessionCreated);
+                Ok(created)
             }
 
-            pub fn delete_session_from_account(&mut self) {
-                let sessions = self.as_mut();
-                let event = panicking(|| delete_session_from_account(sessions));
-                self.notify_on(event).expect("Notification Error");
+            pub async fn create_spectator_session(
+                &mut self,
+                spectator_data: SpectatorData,
+                signature: Option<Vec<u8>>,
+            ) -> Result<(ActorId, SessionData), SessionError> {
+                let owner = match signature {
+                    Some(_) => spectator_data.key,
+                    None => msg::source(),
+                };
+                let created = admit(&[owner])
+                    .await
+                    .and_then(|()| {
+                        create_spectator_session(self.storage_mut(), spectator_data, signature)
+                    })
+                    .inspect_err(|_| refund(msg::source(), msg::value()))?;
+                self.emit(Event::SessionCreated);
+                Ok(created)
             }
 
-            pub fn sessions(&self) -> Vec<(ActorId, SessionData)> {
-                self.as_ref().clone().into_iter().collect()
+            pub async fn create_session_from_template(
+                &mut self,
+                template_id: u32,
+                key: ActorId,
+                signature: Option<Vec<u8>>,
+                signature_valid_until: u64,
+            ) -> Result<(ActorId, SessionData), SessionError> {
+                let owner = match signature {
+                    Some(_) => key,
+                    None => msg::source(),
+                };
+                let created = admit(&[owner])
+                    .await
+                    .and_then(|()| {
+                        create_session_from_template(
+                            self.storage_mut(),
+                            template_id,
+                            key,
+                            signature,
+                            signature_valid_until,
+                        )
+                    })
+                    .inspect_err(|_| refund(msg::source(), msg::value()))?;
+                self.emit(Event::SessionCreated);
+                Ok(created)
             }
 
-            pub fn session_for_the_account(&self, account: ActorId) -> Option<SessionData> {
-                self.as_ref().get(&account).cloned()
+            // Sent by the session key, `signature_data.key` is the owner asked for the session.
+            // Returns the error like the creation calls, so that the attempt is counted
+            pub fn request_session(
+                &mut self,
+                signature_data: SignatureData,
+            ) -> Result<(), SessionError> {
+                let storage = self.storage_mut();
+                let event = request_session(storage, signature_data)?;
+                self.emit(event);
+                Ok(())
             }
-        }
 
-        pub type SessionMap = HashMap<ActorId, SessionData>;
+            pub async fn approve_session_request(
+                &mut self,
+                key: ActorId,
+            ) -> Result<(ActorId, SessionData), SessionError> {
+                let created = admit(&[msg::source()])
+                    .await
+                    .and_then(|()| approve_session_request(self.storage_mut(), key))
+                    .inspect_err(|_| refund(msg::source(), msg::value()))?;
+                self.emit(Event::SessionCreated);
+                Ok(created)
+            }
 
-        #[derive(Debug, Default, Clone, Copy, Encode, Decode, TypeInfo, PartialEq, Eq)]
-        #[codec(crate = sails_rs::scale_codec)]
-        #[scale_info(crate = sails_rs::scale_info)]
-        pub struct Config {
-            pub gas_to_delete_session: u64,
-            pub minimum_session_duration_ms: u64,
-            pub ms_per_block: u64,
-        }
+            // By the owner to reject the request or by the key to withdraw it
+            pub fn cancel_session_request(&mut self, key: ActorId) {
+                let storage = self.storage_mut();
+                panicking(|| cancel_session_request(storage, key));
+            }
 
-        #[derive(Debug, Clone, Encode, Decode, TypeInfo)]
-        #[codec(crate = sails_rs::scale_codec)]
-        #[scale_info(crate = sails_rs::scale_info)]
-        pub enum SessionError {
-            BadSignature,
-            BadPublicKey,
-            VerificationFailed,
-            DurationIsSmall,
-            ThereAreNoAllowedMessages,
-            MessageOnlyForProgram,
-            TooEarlyToDeleteSession,
-            NoSession,
-            AlreadyHaveActiveSession,
-        }
+            pub async fn restore_session(
+                &mut self,
+            ) -> Result<(ActorId, SessionData), SessionError> {
+                let created = admit(&[msg::source()])
+                    .await
+                    .and_then(|()| restore_session(self.storage_mut()))
+                    .inspect_err(|_| refund(msg::source(), msg::value()))?;
+                self.emit(Event::SessionCreated);
+                Ok(created)
+            }
 
-        // This structure is for creating a gaming session, which allows players to predefine certain actions for an account
-        // that will play the game on their behalf for a certain period of time.
-        #[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
-        #[codec(crate = sails_rs::scale_codec)]
-        #[scale_info(crate = sails_rs::scale_info)]
-        pub struct SessionData {
-            // The address of the player who will play on behalf of the user
-            pub key: ActorId,
-            // Until what time the session is valid
-            pub expires: u64,
-            // What messages are allowed to be sent by the account (key)
-            pub allowed_actions: Vec<$actions_enum>,
-            pub expires_at_block: u32,
-        }
+            pub fn reauth_token(&self, owner: ActorId) -> Option<ReauthToken> {
+                self.storage()
+                    .reauth_tokens
+                    .get(&owner)
+                    .filter(|token| token.valid_until_block >= exec::block_height())
+                    .cloned()
+            }
 
-        #[derive(Encode, Decode, TypeInfo)]
-        #[codec(crate = sails_rs::scale_codec)]
-        #[scale_info(crate = sails_rs::scale_info)]
-        pub struct SignatureData {
-            pub key: ActorId,
-            pub duration: u64,
-            pub allowed_actions: Vec<$actions_enum>,
-        }
+            pub fn session_requests(&self, owner: ActorId) -> Vec<(ActorId, SessionRequest)> {
+                session_requests(self.storage(), &owner)
+            }
 
-        pub fn create_session(
-            sessions: &mut SessionMap,
-            config: &Config,
-            signature_data: SignatureData,
-            signature: Option<Vec<u8>>,
-        ) -> Result<Event, SessionError> {
-            if signature_data.duration < config.minimum_session_duration_ms {
-                return Err(SessionError::DurationIsSmall);
+            pub async fn create_team_session(
+                &mut self,
+                signature_data: SignatureData,
+                members: Vec<ActorId>,
+                signatures: Vec<Vec<u8>>,
+            ) -> Result<(ActorId, SessionData), SessionError> {
+                // The team is validated first, so that the registry isn't asked about
+                // more than `MAX_TEAM_SIZE` members
+                let admitted = match check_team(&members, &signatures, &msg::source()) {
+                    Ok(()) => admit(&members).await,
+                    Err(error) => Err(error),
+                };
+                let created = admitted
+                    .and_then(|()| {
+                        create_team_session(
+                            self.storage_mut(),
+                            signature_data,
+                            members,
+                            signatures,
+                        )
+                    })
+                    .inspect_err(|_| refund(msg::source(), msg::value()))?;
+                self.emit(Event::SessionCreated);
+                Ok(created)
             }
 
-            let msg_source = msg::source();
-            let block_timestamp = exec::block_timestamp();
-            let block_height = exec::block_height();
+            // The session key backs the session with the attached value
+            pub fn stake_session(
+                &mut self,
+                session_for_account: ActorId,
+            ) -> Result<(), SessionError> {
+                let storage = self.storage_mut();
+                let event = stake_session(storage, session_for_account)
+                    .inspect_err(|_| refund(msg::source(), msg::value()))?;
+                self.emit(event);
+                Ok(())
+            }
+
+            // Errors leave the state as it was
+            pub fn delete_session_from_program(
+                &mut self,
+                session_for_account: ActorId,
+                generation: u64,
+            ) -> Result<(), SessionError> {
+                let storage = self.storage_mut();
+                let event = delete_session_from_program(storage, session_for_account, generation)?;
+                let heartbeat = heartbeat(storage);
+                for event in event.into_iter().chain(heartbeat) {
+                    self.emit(event);
+                }
+                Ok(())
+            }
 
-            let expires = block_timestamp + signature_data.duration;
+            pub fn delete_session_from_account(&mut self) {
+                let storage = self.storage_mut();
+                let event = panicking(|| delete_session_from_account(storage));
+                self.emit(event);
+            }
 
-            let number_of_blocks =
-                u32::try_from(signature_data.duration.div_ceil(config.ms_per_block))
-                    .expect("Duration is too large");
+            // Also ends the current session of the sender if its key is among `keys`
+            pub fn blacklist_keys(&mut self, keys: Vec<ActorId>) {
+                let storage = self.storage_mut();
+                panicking(|| blacklist_keys(storage, keys));
+            }
 
-            if signature_data.allowed_actions.is_empty() {
-                return Err(SessionError::ThereAreNoAllowedMessages);
+            pub fn unblacklist_keys(&mut self, keys: Vec<ActorId>) {
+                let storage = self.storage_mut();
+                unblacklist_keys(storage, keys);
             }
 
-            let account = match signature {
-                Some(sig_bytes) => {
-                    check_if_session_exists(sessions, &signature_data.key)?;
-                    let pub_key: [u8; 32] = (signature_data.key).into();
-                    let message = SignatureData {
-                        key: msg_source,
-                        duration: signature_data.duration,
-                        allowed_actions: signature_data.allowed_actions.clone(),
-                    }
-                    .encode();
+            // Sent by the owner, or by the key or a team member, who can only narrow the session.
+            // Broadening takes the signature of `ActionsUpdateData` by the other side, see there.
+            // The value tops up the deposit
+            pub fn update_allowed_actions(
+                &mut self,
+                session_for_account: ActorId,
+                add: Vec<$actions_enum>,
+                remove: Vec<$actions_enum>,
+                signature: Option<Vec<u8>>,
+            ) -> Result<(), SessionError> {
+                let storage = self.storage_mut();
+                let event =
+                    update_allowed_actions(storage, session_for_account, add, remove, signature)
+                        .inspect_err(|_| refund(msg::source(), msg::value()))?;
+                self.emit(event);
+                Ok(())
+            }
 
-                    let complete_message =
-                        [b"<Bytes>".to_vec(), message, b"</Bytes>".to_vec()].concat();
+            // `session_for_account` is the account of the sender or of one of its teams
+            pub fn set_session_label(&mut self, session_for_account: ActorId, label: String) {
+                let storage = self.storage_mut();
+                panicking(|| set_session_label(storage, session_for_account, label));
+            }
 
-                    verify(&sig_bytes, complete_message, pub_key)?;
-                    sessions.entry(signature_data.key).insert(SessionData {
-                        key: msg_source,
-                        expires,
-                        allowed_actions: signature_data.allowed_actions,
-                        expires_at_block: block_height + number_of_blocks,
-                    });
-                    signature_data.key
-                }
-                None => { 
-                    check_if_session_exists(sessions, &msg_source)?;
-                    sessions.entry(msg_source).insert(SessionData {
-                        key: signature_data.key,
-                        expires,
-                        allowed_actions: signature_data.allowed_actions,
-                        expires_at_block: block_height + number_of_blocks,
-                    });
-                    msg_source
-                }
-            };
+            // `session_for_account` is the account of the sender or of one of its teams,
+            // an empty name takes the session out of its group
+            pub fn set_session_group(&mut self, session_for_account: ActorId, name: String) {
+                let storage = self.storage_mut();
+                panicking(|| set_session_group(storage, session_for_account, name));
+            }
 
-            let request = [
-                "Session".encode(),
-                "DeleteSessionFromProgram".to_string().encode(),
-                (account).encode(),
-            ]
-            .concat();
+            // Ends every session in the group of the sender, including the ones of its teams
+            pub fn revoke_group(&mut self, name: String) {
+                let storage = self.storage_mut();
+                let event = revoke_group(storage, name);
+                self.emit(event);
+            }
 
-            msg::send_bytes_with_gas_delayed(
-                exec::program_id(),
-                request,
-                config.gas_to_delete_session,
-                0,
-                number_of_blocks,
-            )
-            .expect("Error in sending message");
+            pub fn delete_team_session(&mut self, team: ActorId) {
+                let storage = self.storage_mut();
+                let event = panicking(|| delete_team_session(storage, team));
+                self.emit(event);
+            }
 
-            Ok(Event::SessionCreated)
-        }
+            pub fn invalidate_scope(&mut self, scope_id: u64) {
+                let storage = self.storage_mut();
+                let event = panicking(|| invalidate_scope(storage, scope_id));
+                self.emit(event);
+            }
 
-        pub fn delete_session_from_program(
-            sessions: &mut SessionMap,
-            session_for_account: ActorId,
-        ) -> Result<Event, SessionError> {
-            if msg::source() != exec::program_id() {
-                return Err(SessionError::MessageOnlyForProgram);
+            pub fn publish_template(&mut self, template: SessionTemplate) {
+                let storage = self.storage_mut();
+                let event = panicking(|| publish_template(storage, template));
+                self.emit(event);
             }
 
-            if let Some(session) = sessions.remove(&session_for_account) {
-                if session.expires_at_block > exec::block_height() {
-                    return Err(SessionError::TooEarlyToDeleteSession);
-                }
+            pub fn retire_template(&mut self, template_id: u32) {
+                let storage = self.storage_mut();
+                let event = panicking(|| retire_template(storage, template_id));
+                self.emit(event);
             }
-            Ok(Event::SessionDeleted)
-        }
 
-        pub fn delete_session_from_account(
-            sessions: &mut SessionMap,
-        ) -> Result<Event, SessionError> {
-            if sessions.remove(&msg::source()).is_none() {
-                return Err(SessionError::NoSession);
+            // `sessions` and `teams` are the outputs of the queries of the previous program
+            pub fn import_sessions(
+                &mut self,
+                from: ActorId,
+                sessions: Vec<(ActorId, SessionData)>,
+                teams: Vec<(ActorId, Vec<ActorId>)>,
+            ) {
+                let storage = self.storage_mut();
+                let event = panicking(|| import_sessions(storage, from, sessions, teams));
+                self.emit(event);
             }
-            Ok(Event::SessionDeleted)
-        }
 
-        fn verify<P: AsRef<[u8]>, M: AsRef<[u8]>>(
-            signature: &[u8],
-            message: M,
-            pubkey: P,
-        ) -> Result<(), SessionError> {
-            let signature =
-                Signature::from_bytes(signature).map_err(|_| SessionError::BadSignature)?;
-            let pub_key =
-                PublicKey::from_bytes(pubkey.as_ref()).map_err(|_| SessionError::BadPublicKey)?;
-            pub_key
-                .verify_simple(b"substrate", message.as_ref(), &signature)
-                .map(|_| ())
-                .map_err(|_| SessionError::VerificationFailed)
-        }
+            // Sends the stake to `recipient` and ends the session
+            pub fn slash_stake(&mut self, session_for_account: ActorId, recipient: ActorId) {
+                let storage = self.storage_mut();
+                let event = panicking(|| slash_stake(storage, session_for_account, recipient));
+                self.emit(event);
+            }
 
-        fn check_if_session_exists(
-            session_map: &HashMap<ActorId, SessionData>,
-            account: &ActorId,
-        ) -> Result<(), SessionError> {
-            if let Some(SessionData {
-                key: _,
-                expires: _,
-                allowed_actions: _,
-                expires_at_block,
-            }) = session_map.get(account)
-            {
-                if *expires_at_block > exec::block_height() {
-                    return Err(SessionError::AlreadyHaveActiveSession);
+            // 0 restarts the measurement from the next executed deletion
+            pub fn recalibrate_deletion_gas(&mut self, baseline: u64) {
+                let storage = self.storage_mut();
+                let event = panicking(|| recalibrate_deletion_gas(storage, baseline));
+                self.emit(event);
+            }
+
+            // Gas the next scheduled deletion is sent with
+            pub fn deletion_gas(&self) -> u64 {
+                deletion_gas(self.storage())
+            }
+
+            pub fn set_arbiter(&mut self, arbiter: Option<ActorId>) {
+                let storage = self.storage_mut();
+                panicking(|| set_arbiter(storage, arbiter));
+            }
+
+            pub fn delegation_history(&self, owner: ActorId) -> Vec<DelegationRecord> {
+                self.storage()
+                    .delegation_history
+                    .get(&owner)
+                    .cloned()
+                    .unwrap_or_default()
+            }
+
+            pub fn blacklisted_keys(&self, owner: ActorId) -> Vec<ActorId> {
+                self.storage()
+                    .blacklisted_keys
+                    .get(&owner)
+                    .map(|keys| keys.iter().copied().collect())
+                    .unwrap_or_default()
+            }
+
+            // Lets owners vet a piloting service before delegating to its key
+            pub fn key_reputation(&self, key: ActorId) -> KeyReputation {
+                self.storage()
+                    .key_reputation
+                    .get(&key)
+                    .copied()
+                    .unwrap_or_default()
+            }
+
+            pub fn session_stake(&self, session_for_account: ActorId) -> Option<Stake> {
+                self.storage().stakes.get(&session_for_account).copied()
+            }
+
+            // 0 blocks removes the timelock of the action
+            pub fn set_action_timelock(&mut self, action: $actions_enum, blocks: u32) {
+                let storage = self.storage_mut();
+                let event = panicking(|| set_action_timelock(storage, action, blocks));
+                self.emit(event);
+            }
+
+            pub fn action_timelocks(&self) -> Vec<($actions_enum, u32)> {
+                self.storage().action_timelocks.clone()
+            }
+
+            pub fn freeze(&mut self) {
+                let storage = self.storage_mut();
+                let event = panicking(|| freeze(storage));
+                self.emit(event);
+            }
+
+            pub fn unfreeze(&mut self) {
+                let storage = self.storage_mut();
+                let event = panicking(|| unfreeze(storage));
+                self.emit(event);
+            }
+
+            pub fn is_frozen(&self) -> bool {
+                self.storage().frozen
+            }
+
+            pub fn disable_action(&mut self, action: $actions_enum) {
+                let storage = self.storage_mut();
+                let event = panicking(|| disable_action(storage, action));
+                self.emit(event);
+            }
+
+            pub fn enable_action(&mut self, action: $actions_enum) {
+                let storage = self.storage_mut();
+                let event = panicking(|| enable_action(storage, action));
+                self.emit(event);
+            }
+
+            pub fn disabled_actions(&self) -> Vec<$actions_enum> {
+                self.storage().disabled_actions.clone()
+            }
+
+            pub fn add_trusted_forwarder(&mut self, forwarder: ActorId) {
+                let storage = self.storage_mut();
+                let event = panicking(|| add_trusted_forwarder(storage, forwarder));
+                self.emit(event);
+            }
+
+            pub fn remove_trusted_forwarder(&mut self, forwarder: ActorId) {
+                let storage = self.storage_mut();
+                let event = panicking(|| remove_trusted_forwarder(storage, forwarder));
+                self.emit(event);
+            }
+
+            pub fn trusted_forwarders(&self) -> Vec<ActorId> {
+                self.storage().trusted_forwarders.iter().copied().collect()
+            }
+
+            pub fn templates(&self) -> Vec<(u32, SessionTemplate)> {
+                self.storage().templates.clone().into_iter().collect()
+            }
+
+            // The account team sessions of `members` are stored for
+            pub fn team_id(&self, members: Vec<ActorId>) -> ActorId {
+                team_id(&members)
+            }
+
+            // Sessions of the owner, including the ones of its teams, labeled with `label`
+            pub fn sessions_by_label(
+                &self,
+                owner: ActorId,
+                label: String,
+            ) -> Vec<(ActorId, SessionData)> {
+                sessions_by_label(self.storage(), owner, &label)
+            }
+
+            pub fn sessions_in_group(
+                &self,
+                owner: ActorId,
+                name: String,
+            ) -> Vec<(ActorId, SessionData)> {
+                sessions_in_group(self.storage(), &owner, &name)
+            }
+
+            // The delayed deletion messages not executed yet, the earliest first
+            pub fn pending_deletions(&self) -> Vec<PendingDeletion> {
+                pending_deletions(self.storage())
+            }
+
+            // Sessions that won't ever be deleted by the program, should always be empty
+            pub fn sessions_without_deletion(&self) -> Vec<ActorId> {
+                sessions_without_deletion(self.storage())
+            }
+
+            // For tuning `minimum_session_duration_ms` and sponsorship budgets
+            pub fn session_duration_histogram(&self) -> DurationHistogram {
+                session_duration_histogram(self.storage())
+            }
+
+            pub fn sessions(&self) -> Vec<(ActorId, SessionData)> {
+                self.as_ref().clone().into_iter().collect()
+            }
+
+            // The members of each team with a session, by the id of the team
+            pub fn teams(&self) -> Vec<(ActorId, Vec<ActorId>)> {
+                self.storage().teams.clone().into_iter().collect()
+            }
+
+            pub fn session_for_the_account(&self, account: ActorId) -> Option<SessionData> {
+                self.as_ref().get(&account).cloned()
+            }
+
+            // Milliseconds and blocks until the session expires, `None` if it already has
+            pub fn session_remaining(&self, account: ActorId) -> Option<(u64, u64)> {
+                session_remaining(self.storage(), &account)
+            }
+
+            pub fn gasless_status(&self, account: ActorId) -> GaslessStatus {
+                gasless_status(self.storage(), &account)
+            }
+
+            // `None` if `key` can use the session of `owner` for `action` right now
+            pub fn why_not_allowed(
+                &self,
+                owner: ActorId,
+                key: ActorId,
+                action: $actions_enum,
+            ) -> Option<SessionError> {
+                check_authorization(self.storage(), &owner, &key, &action).err()
+            }
+
+            // Pre-flight of `create_session`, see `validate_session_request`
+            pub fn validate_session_request(
+                &self,
+                signature_data: SignatureData,
+                signature: Option<Vec<u8>>,
+            ) -> Result<(), SessionError> {
+                validate_session_request(self.storage(), signature_data, signature)
+            }
+
+            pub fn service_info(&self) -> ServiceInfo {
+                service_info()
+            }
+        }
+
+        pub type SessionMap = HashMap<ActorId, SessionData>;
+
+        pub const MAX_TEAM_SIZE: usize = 16;
+
+        pub const MAX_LABEL_LENGTH: usize = 32;
+
+        pub const MAX_GROUP_NAME_LENGTH: usize = 32;
+
+        pub const MAX_DELEGATION_HISTORY: usize = 32;
+
+        pub const MAX_BLACKLISTED_KEYS: usize = 64;
+
+        // A day, the longest a signature of a session can wait to be used
+        pub const MAX_SIGNATURE_VALIDITY_MS: u64 = 86_400_000;
+
+        // About 30 minutes with 3 second blocks
+        pub const SESSION_REQUEST_TTL_BLOCKS: u32 = 600;
+
+        // About a minute with 3 second blocks, the longest an expired session and its deposit
+        // are kept by a pending deletion it adopted
+        pub const ADOPTED_DELETION_SLACK_BLOCKS: u32 = 20;
+
+        // 5 minutes, 15 minutes, 1 hour, 6 hours and 1 day
+        pub const DURATION_HISTOGRAM_BOUNDS_MS: [u64; 5] =
+            [300_000, 900_000, 3_600_000, 21_600_000, 86_400_000];
+
+        // The route the service is exposed under, `Session` unless `service_route` is passed
+        // to the macro. Sails derives it from the name of the program method returning
+        // the service, so nothing checks that the two agree: with a wrong route
+        // the scheduled deletions fail to decode and sessions are never deleted
+        pub const SESSION_SERVICE_ROUTE: &str = $service_route;
+
+        // Encodes the `DeleteSessionFromProgram` call to this service by hand,
+        // used to schedule the deletion of a session
+        pub struct DeleteSessionFromProgram(());
+
+        impl DeleteSessionFromProgram {
+            pub const ROUTE: &'static str = "DeleteSessionFromProgram";
+
+            pub fn encode_call(session_for_account: ActorId, generation: u64) -> Vec<u8> {
+                // Fails to compile if the exported method is renamed or its parameters change
+                let _: fn(&mut SessionService, ActorId, u64) -> Result<(), SessionError> =
+                    SessionService::delete_session_from_program;
+                [
+                    SESSION_SERVICE_ROUTE.encode(),
+                    Self::ROUTE.encode(),
+                    (session_for_account, generation).encode(),
+                ]
+                .concat()
+            }
+        }
+
+        #[derive(Debug, Default, Clone, Copy, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct Config {
+            pub gas_to_delete_session: u64,
+            pub minimum_session_duration_ms: u64,
+            pub ms_per_block: u64,
+            // 0 means there is no limit
+            pub max_attempts_per_block: u32,
+            // Value to be attached per byte of the stored session, 0 disables the deposit
+            pub deposit_per_byte: u128,
+            // Sessions created by the owner's own transaction must also be signed by the session key,
+            // so that a phished transaction alone can't delegate to a key of the attacker
+            pub require_key_signature: bool,
+            // Program asked whether an owner may delegate before each session is created,
+            // it receives the encoded `ActorId` of the owner and replies with a `bool`
+            pub eligibility_registry: Option<ActorId>,
+            // Owners get a `SessionNotification` message besides the events
+            pub notify_owners: bool,
+            // Minimum number of blocks between `SessionsHeartbeat` events, 0 disables them.
+            // They are emitted by the scheduled deletions, so their absence means these stalled
+            pub heartbeat_interval_blocks: u32,
+            // Each authorized action pushes the expiry of the session this far from now,
+            // 0 disables it. Sessions aren't extended past `max_session_lifetime_ms` from creation
+            pub sliding_expiration_ms: u64,
+            pub max_session_lifetime_ms: u64,
+            // Blocks an expired session can be restored for with `restore_session`, 0 disables it
+            pub reauth_window_blocks: u32,
+            // Deletions get the gas measured for the executed ones plus this percentage,
+            // 0 keeps sending `gas_to_delete_session`
+            pub deletion_gas_margin_percent: u32,
+        }
+
+        impl Config {
+            pub fn validate(&self) -> Result<(), SessionError> {
+                // Sliding sessions need a lifetime to stop at, or they could be kept forever
+                if self.ms_per_block == 0
+                    || (self.sliding_expiration_ms != 0 && self.max_session_lifetime_ms == 0)
+                {
+                    return Err(SessionError::InvalidConfig);
+                }
+                Ok(())
+            }
+        }
+
+        #[derive(Debug, Clone, Encode, Decode, TypeInfo)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub enum SessionError {
+            BadSignature,
+            BadPublicKey,
+            VerificationFailed,
+            DurationIsSmall {
+                provided: u64,
+                required: u64,
+            },
+            DurationIsTooLarge,
+            ThereAreNoAllowedMessages,
+            MessageOnlyForProgram,
+            // Retired, no longer returned: a deletion arriving before the session expires
+            // is rescheduled instead. Kept so that code 8 and the encoding of the errors
+            // after it stay the same
+            TooEarlyToDeleteSession {
+                current_block: u32,
+                expires_at_block: u32,
+            },
+            NoSession,
+            AlreadyHaveActiveSession {
+                current_block: u32,
+                expires_at_block: u32,
+            },
+            SessionKeyIsOwner,
+            AllowedActionsAreNotCanonical,
+            KeyAlreadyInUse,
+            SignatureAlreadyUsed,
+            FailedToScheduleDeletion,
+            InvalidConfig,
+            TooManyAttempts {
+                limit: u32,
+            },
+            InsufficientDeposit {
+                provided: u128,
+                required: u128,
+            },
+            KeySignatureRequired,
+            NotAdmin,
+            ScopeIsInvalidated,
+            InvalidTeam,
+            NotTeamMember,
+            NotSessionKey,
+            ActionIsNotAllowed,
+            NoTemplate,
+            UntrustedForwarder,
+            LabelIsTooLong,
+            NotEligible,
+            EligibilityCheckFailed,
+            ActionIsTimelocked {
+                usable_from_block: u32,
+            },
+            NothingToStake,
+            NoStake,
+            TransferFailed,
+            KeyIsBlacklisted,
+            BlacklistIsFull,
+            ActionIsDisabled,
+            SessionsAreFrozen,
+            ActionIsCoolingDown {
+                usable_from_block: u32,
+            },
+            SessionExpired {
+                expires_at_block: u32,
+            },
+            NoSessionRequest,
+            NoReauthToken,
+            GroupNameIsTooLong,
+            DurationIsAmbiguous,
+            NotFrozen,
+            InvalidImportSource,
+            NotDepositor,
+            SignatureExpired,
+            SignatureValidityIsTooLong,
+        }
+
+        impl SessionError {
+            // Stable across releases, so frontends can branch on errors without their names.
+            // A code is never reused, new errors take the next one
+            pub fn code(&self) -> u16 {
+                match self {
+                    Self::BadSignature => 1,
+                    Self::BadPublicKey => 2,
+                    Self::VerificationFailed => 3,
+                    Self::DurationIsSmall { .. } => 4,
+                    Self::DurationIsTooLarge => 5,
+                    Self::ThereAreNoAllowedMessages => 6,
+                    Self::MessageOnlyForProgram => 7,
+                    Self::TooEarlyToDeleteSession { .. } => 8,
+                    Self::NoSession => 9,
+                    Self::AlreadyHaveActiveSession { .. } => 10,
+                    Self::SessionKeyIsOwner => 11,
+                    Self::AllowedActionsAreNotCanonical => 12,
+                    Self::KeyAlreadyInUse => 13,
+                    Self::SignatureAlreadyUsed => 14,
+                    Self::FailedToScheduleDeletion => 15,
+                    Self::InvalidConfig => 16,
+                    Self::TooManyAttempts { .. } => 17,
+                    Self::InsufficientDeposit { .. } => 18,
+                    Self::KeySignatureRequired => 19,
+                    Self::NotAdmin => 20,
+                    Self::ScopeIsInvalidated => 21,
+                    Self::InvalidTeam => 22,
+                    Self::NotTeamMember => 23,
+                    Self::NotSessionKey => 24,
+                    Self::ActionIsNotAllowed => 25,
+                    Self::NoTemplate => 26,
+                    Self::UntrustedForwarder => 27,
+                    Self::LabelIsTooLong => 28,
+                    Self::NotEligible => 29,
+                    Self::EligibilityCheckFailed => 30,
+                    Self::ActionIsTimelocked { .. } => 31,
+                    Self::NothingToStake => 32,
+                    Self::NoStake => 33,
+                    Self::TransferFailed => 34,
+                    Self::KeyIsBlacklisted => 35,
+                    Self::BlacklistIsFull => 36,
+                    Self::ActionIsDisabled => 37,
+                    Self::SessionsAreFrozen => 38,
+                    Self::ActionIsCoolingDown { .. } => 39,
+                    Self::SessionExpired { .. } => 40,
+                    Self::NoSessionRequest => 41,
+                    Self::NoReauthToken => 42,
+                    Self::GroupNameIsTooLong => 43,
+                    Self::DurationIsAmbiguous => 44,
+                    Self::NotFrozen => 45,
+                    Self::InvalidImportSource => 46,
+                    Self::NotDepositor => 47,
+                    Self::SignatureExpired => 48,
+                    Self::SignatureValidityIsTooLong => 49,
+                }
+            }
+        }
+
+        // This structure is for creating a gaming session, which allows players to predefine certain actions for an account
+        // that will play the game on their behalf for a certain period of time.
+        #[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct SessionData {
+            // The address of the player who will play on behalf of the user
+            pub key: ActorId,
+            // Until what time the session is valid
+            pub expires: u64,
+            // What messages are allowed to be sent by the account (key)
+            pub allowed_actions: Vec<$actions_enum>,
+            pub expires_at_block: u32,
+            // Distinguishes this session from the previous and next ones of the same account,
+            // so that a deletion scheduled for an older session can't remove it
+            pub generation: u64,
+            // Tournament or match the session is bound to, see `invalidate_scope`
+            pub scope_id: Option<u64>,
+            // Timelocked actions are counted from this block, see `set_action_timelock`
+            pub created_at_block: u32,
+            // Minimum number of blocks between consecutive uses of the actions
+            pub action_cooldowns: Vec<($actions_enum, u32)>,
+            // The length the session was created for, in milliseconds and in blocks
+            pub duration: u64,
+            pub duration_blocks: u32,
+        }
+
+        impl SessionData {
+            // The timestamp is the source of truth for the session validity,
+            // the block height is only consulted for sessions stored without it
+            pub fn is_expired(&self) -> bool {
+                if self.expires != 0 {
+                    self.expires <= exec::block_timestamp()
+                } else {
+                    self.expires_at_block <= exec::block_height()
+                }
+            }
+        }
+
+        #[derive(Encode, Decode, TypeInfo)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct SignatureData {
+            pub key: ActorId,
+            pub duration: u64,
+            pub allowed_actions: Vec<$actions_enum>,
+            pub scope_id: Option<u64>,
+            // Minimum number of blocks between consecutive uses of the actions
+            pub action_cooldowns: Vec<($actions_enum, u32)>,
+            // Alternative to `duration`, exactly one of the two is set
+            pub duration_blocks: u32,
+            // Timestamp the signatures over the data are accepted until, see
+            // `MAX_SIGNATURE_VALIDITY_MS`. Ignored when nothing is signed
+            pub signature_valid_until: u64,
+        }
+
+        // The shorter payload of a spectator session, which always allows the read-only actions
+        #[derive(Encode, Decode, TypeInfo)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct SpectatorData {
+            pub key: ActorId,
+            pub duration: u64,
+            pub signature_valid_until: u64,
+        }
+
+        // Actions designated by the program as not changing its state, in the canonical order
+        pub fn read_only_actions() -> Vec<$actions_enum> {
+            let mut actions: Vec<$actions_enum> = vec![$($read_only),*];
+            actions.sort_by_key(|action| action.encode());
+            actions.dedup();
+            actions
+        }
+
+        // Published by the admin, so that frontends apply the same policy
+        // and the owner only signs the number of the template
+        #[derive(Debug, Clone, Encode, Decode, TypeInfo, PartialEq, Eq)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct SessionTemplate {
+            pub allowed_actions: Vec<$actions_enum>,
+            pub duration: u64,
+            pub scope_id: Option<u64>,
+            pub action_cooldowns: Vec<($actions_enum, u32)>,
+        }
+
+        #[derive(Encode, Decode, TypeInfo)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct TemplateData {
+            pub template_id: u32,
+            pub key: ActorId,
+            pub valid_until: u64,
+        }
+
+        // Signed by the other side to broaden a session: by the owner when the key sends it,
+        // by the key when the owner does with `require_key_signature`. `key` is the sender,
+        // `generation` binds the signature to that session only
+        #[derive(Encode, Decode, TypeInfo)]
+        #[codec(crate = sails_rs::scale_codec)]
+        #[scale_info(crate = sails_rs::scale_info)]
+        pub struct ActionsUpdateData {
+            pub key: ActorId,
+            pub generation: u64,
+            pub add: Vec<$actions_enum>,
+            pub remove: Vec<$actions_enum>,
+        }
+
+        // What authorizes the delegation besides the sender of the message
+        enum SessionApproval {
+            // The message signed off-chain by the owner, sent by the session key
+            OwnerSignature {
+                message: Vec<u8>,
+                signature: Vec<u8>,
+            },
+            // Sent by the owner, optionally with the consent signed by the session key
+            OwnerTransaction { key_signature: Option<Vec<u8>> },
+            // Signed off-chain by every member of a team, the message is sent by the session key
+            Team {
+                members: Vec<ActorId>,
+                signatures: Vec<Vec<u8>>,
+            },
+            // A spectator session sent by the owner. The key signature is never required
+            // as nothing can be changed on behalf of the owner
+            ReadOnlyTransaction,
+            // Sent by the owner approving a request, the key consented by sending the request
+            RequestedByKey,
+            // Sent by the owner restoring an expired session the key has already consented to
+            Reauthorization,
+        }
+
+        pub fn create_session(
+            storage: &mut Storage,
+            signature_data: SignatureData,
+            signature: Option<Vec<u8>>,
+        ) -> Result<(ActorId, SessionData), SessionError> {
+            let approval = owner_approval(&signature_data, signature);
+            create_approved_session(storage, signature_data, approval)
+        }
+
+        // Runs every check of `create_session` for the sender and the attached value,
+        // except for the eligibility registry, without changing anything
+        pub fn validate_session_request(
+            storage: &Storage,
+            signature_data: SignatureData,
+            signature: Option<Vec<u8>>,
+        ) -> Result<(), SessionError> {
+            if storage.frozen {
+                return Err(SessionError::SessionsAreFrozen);
+            }
+            check_attempts(storage, &msg::source())?;
+            let approval = owner_approval(&signature_data, signature);
+            prepare_session(storage, signature_data, approval).map(|_| ())
+        }
+
+        fn owner_approval(
+            signature_data: &SignatureData,
+            signature: Option<Vec<u8>>,
+        ) -> SessionApproval {
+            match signature {
+                Some(signature) => SessionApproval::OwnerSignature {
+                    message: delegation_message(signature_data, msg::source()),
+                    signature,
+                },
+                None => SessionApproval::OwnerTransaction {
+                    key_signature: None,
+                },
+            }
+        }
+
+        pub fn create_session_signed_by_key(
+            storage: &mut Storage,
+            signature_data: SignatureData,
+            key_signature: Vec<u8>,
+        ) -> Result<(ActorId, SessionData), SessionError> {
+            let approval = SessionApproval::OwnerTransaction {
+                key_signature: Some(key_signature),
+            };
+            create_approved_session(storage, signature_data, approval)
+        }
+
+        pub fn create_spectator_session(
+            storage: &mut Storage,
+            spectator_data: SpectatorData,
+            signature: Option<Vec<u8>>,
+        ) -> Result<(ActorId, SessionData), SessionError> {
+            let signature_data = SignatureData {
+                key: spectator_data.key,
+                duration: spectator_data.duration,
+                allowed_actions: read_only_actions(),
+                scope_id: None,
+                action_cooldowns: Vec::new(),
+                duration_blocks: 0,
+                signature_valid_until: spectator_data.signature_valid_until,
+            };
+            let approval = match signature {
+                Some(signature) => SessionApproval::OwnerSignature {
+                    message: SpectatorData {
+                        key: msg::source(),
+                        duration: spectator_data.duration,
+                        signature_valid_until: spectator_data.signature_valid_until,
+                    }
+                    .encode(),
+                    signature,
+                },
+                None => SessionApproval::ReadOnlyTransaction,
+            };
+            let (account, session) = create_approved_session(storage, signature_data, approval)?;
+            storage.spectator_sessions.insert(account);
+            Ok((account, session))
+        }
+
+        // The owner signs only `TemplateData` with the session key in place of `key`
+        pub fn create_session_from_template(
+            storage: &mut Storage,
+            template_id: u32,
+            key: ActorId,
+            signature: Option<Vec<u8>>,
+            signature_valid_until: u64,
+        ) -> Result<(ActorId, SessionData), SessionError> {
+            let template = storage
+                .templates
+                .get(&template_id)
+                .ok_or(SessionError::NoTemplate)?;
+            let signature_data = SignatureData {
+                key,
+                duration: template.duration,
+                allowed_actions: template.allowed_actions.clone(),
+                scope_id: template.scope_id,
+                action_cooldowns: template.action_cooldowns.clone(),
+                duration_blocks: 0,
+                signature_valid_until,
+            };
+            let approval = match signature {
+                Some(signature) => SessionApproval::OwnerSignature {
+                    message: TemplateData {
+                        template_id,
+                        key: msg::source(),
+                        valid_until: signature_valid_until,
+                    }
+                    .encode(),
+                    signature,
+                },
+                None => SessionApproval::OwnerTransaction {
+                    key_signature: None,
+                },
+            };
+            create_approved_session(storage, signature_data, approval)
+        }
+
+        // `signature_data.key` is the id of the team, see `team_id`
+        pub fn create_team_session(
+            storage: &mut Storage,
+            signature_data: SignatureData,
+            members: Vec<ActorId>,
+            signatures: Vec<Vec<u8>>,
+        ) -> Result<(ActorId, SessionData), SessionError> {
+            let approval = SessionApproval::Team {
+                members,
+                signatures,
+            };
+            create_approved_session(storage, signature_data, approval)
+        }
+
+        fn create_approved_session(
+            storage: &mut Storage,
+            signature_data: SignatureData,
+            approval: SessionApproval,
+        ) -> Result<(ActorId, SessionData), SessionError> {
+            let msg_source = msg::source();
+
+            // Checked again, the program may have been frozen while the registry was asked
+            if storage.frozen {
+                return Err(SessionError::SessionsAreFrozen);
+            }
+
+            // Nothing is stored until every check has passed and the deletion is scheduled,
+            // so a failure at any step leaves the state untouched
+            let PreparedSession {
+                account,
+                session,
+                signature_hashes,
+                signatures_valid_until,
+                team_members,
+                deposit,
+                adopted_deletion,
+            } = prepare_session(storage, signature_data, approval)?;
+
+            match adopted_deletion {
+                Some(adopted) => adopt_deletion(storage, account, adopted, session.generation),
+                None => {
+                    schedule_deletion(
+                        storage,
+                        account,
+                        session.generation,
+                        session.expires_at_block,
+                    )?;
+                }
+            }
+
+            // Replaying the signatures once the session is gone would restore it, so they are
+            // remembered for as long as they are valid and forgotten once they can't be used
+            if !signature_hashes.is_empty() {
+                let now = exec::block_timestamp();
+                storage
+                    .consumed_signatures
+                    .retain(|_, remembered_until| *remembered_until > now);
+                for signature_hash in signature_hashes {
+                    storage
+                        .consumed_signatures
+                        .insert(signature_hash, signatures_valid_until);
+                }
+            }
+
+            storage.insert_session(account, session.clone());
+            if let Some(members) = team_members {
+                storage.teams.insert(account, members);
+            }
+            if deposit > 0 {
+                storage.deposits.insert(
+                    account,
+                    Deposit {
+                        depositor: msg_source,
+                        amount: deposit,
+                    },
+                );
+            }
+
+            Ok((account, session))
+        }
+
+        struct PreparedSession {
+            account: ActorId,
+            session: SessionData,
+            signature_hashes: Vec<[u8; 32]>,
+            // Until when the signatures are remembered, the end of their validity
+            signatures_valid_until: u64,
+            team_members: Option<Vec<ActorId>>,
+            deposit: u128,
+            // The deletion scheduled for an earlier session that the session takes over
+            adopted_deletion: Option<ScheduledDeletion>,
+        }
+
+        // Every check of the session creation, nothing is changed
+        fn prepare_session(
+            storage: &Storage,
+            signature_data: SignatureData,
+            approval: SessionApproval,
+        ) -> Result<PreparedSession, SessionError> {
+            let config = storage.config;
+            let msg_source = msg::source();
+
+            let duration = session_duration(&config, &signature_data)?;
+            if duration < config.minimum_session_duration_ms {
+                return Err(SessionError::DurationIsSmall {
+                    provided: duration,
+                    required: config.minimum_session_duration_ms,
+                });
+            }
+
+            let expires = exec::block_timestamp()
+                .checked_add(duration)
+                .ok_or(SessionError::DurationIsTooLarge)?;
+            let expires_at_block = expiration_block(&config, expires)?;
+            let duration_blocks = expires_at_block - exec::block_height();
+
+            check_allowed_actions(&signature_data.allowed_actions)?;
+
+            if signature_data
+                .scope_id
+                .is_some_and(|scope_id| storage.invalidated_scopes.contains(&scope_id))
+            {
+                return Err(SessionError::ScopeIsInvalidated);
+            }
+
+            // In all paths `key` and `msg_source` are the two sides of the delegation
+            if signature_data.key == msg_source {
+                return Err(SessionError::SessionKeyIsOwner);
+            }
+
+            let (account, key, signature_hashes, team_members) = match approval {
+                SessionApproval::OwnerSignature { message, signature } => {
+                    check_if_session_exists(&storage.sessions, &signature_data.key)?;
+                    check_key_is_free(storage, &signature_data.key, &msg_source)?;
+                    check_signature_validity(signature_data.signature_valid_until)?;
+                    let signature_hash =
+                        check_signature(storage, &signature_data.key, &message, &signature)?;
+                    (signature_data.key, msg_source, vec![signature_hash], None)
+                }
+                SessionApproval::OwnerTransaction { key_signature } => {
+                    check_if_session_exists(&storage.sessions, &msg_source)?;
+                    check_key_is_free(storage, &msg_source, &signature_data.key)?;
+                    let signature_hashes = match key_signature {
+                        Some(sig_bytes) => {
+                            check_signature_validity(signature_data.signature_valid_until)?;
+                            let message = delegation_message(&signature_data, msg_source);
+                            vec![check_signature(
+                                storage,
+                                &signature_data.key,
+                                &message,
+                                &sig_bytes,
+                            )?]
+                        }
+                        None if config.require_key_signature => {
+                            return Err(SessionError::KeySignatureRequired)
+                        }
+                        None => Vec::new(),
+                    };
+                    (msg_source, signature_data.key, signature_hashes, None)
+                }
+                SessionApproval::Team {
+                    members,
+                    signatures,
+                } => {
+                    check_team(&members, &signatures, &msg_source)?;
+                    if signature_data.key != team_id(&members) {
+                        return Err(SessionError::InvalidTeam);
+                    }
+                    check_if_session_exists(&storage.sessions, &signature_data.key)?;
+                    check_key_is_free(storage, &signature_data.key, &msg_source)?;
+                    check_signature_validity(signature_data.signature_valid_until)?;
+                    // The members are a part of the signed data, so a signature of a member
+                    // can't be used for a personal session or for another team
+                    let message = [
+                        delegation_message(&signature_data, msg_source),
+                        members.encode(),
+                    ]
+                    .concat();
+                    let signature_hashes = members
+                        .iter()
+                        .zip(&signatures)
+                        .map(|(member, sig_bytes)| {
+                            check_signature(storage, member, &message, sig_bytes)
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    (signature_data.key, msg_source, signature_hashes, Some(members))
+                }
+                SessionApproval::ReadOnlyTransaction
+                | SessionApproval::RequestedByKey
+                | SessionApproval::Reauthorization => {
+                    check_if_session_exists(&storage.sessions, &msg_source)?;
+                    check_key_is_free(storage, &msg_source, &signature_data.key)?;
+                    (msg_source, signature_data.key, Vec::new(), None)
+                }
+            };
+
+            if storage
+                .blacklisted_keys
+                .get(&account)
+                .is_some_and(|keys| keys.contains(&key))
+            {
+                return Err(SessionError::KeyIsBlacklisted);
+            }
+
+            // The delayed message can't be cancelled when a session is deleted early,
+            // so a later session of the account takes it over if it fires soon enough
+            // after the session expires instead of paying for one more message. The session
+            // still gets a generation of its own, so that nothing signed for the earlier one
+            // applies to it
+            let adopted_deletion = storage
+                .scheduled_deletions
+                .get(&account)
+                .and_then(|deletions| {
+                    deletions
+                        .iter()
+                        .filter(|deletion| {
+                            deletion.at_block >= expires_at_block
+                                && deletion.at_block - expires_at_block
+                                    <= ADOPTED_DELETION_SLACK_BLOCKS
+                        })
+                        .min_by_key(|deletion| deletion.at_block)
+                })
+                .copied();
+            let generation = storage.last_generation + 1;
+            let signatures_valid_until = signature_data.signature_valid_until;
+
+            let session = SessionData {
+                key,
+                expires,
+                allowed_actions: signature_data.allowed_actions,
+                expires_at_block,
+                generation,
+                scope_id: signature_data.scope_id,
+                created_at_block: exec::block_height(),
+                action_cooldowns: signature_data.action_cooldowns,
+                duration,
+                duration_blocks,
+            };
+
+            let deposit = msg::value();
+            let required_deposit =
+                config.deposit_per_byte.saturating_mul(session.encoded_size() as u128);
+            if deposit < required_deposit {
+                return Err(SessionError::InsufficientDeposit {
+                    provided: deposit,
+                    required: required_deposit,
+                });
+            }
+
+            Ok(PreparedSession {
+                account,
+                session,
+                signature_hashes,
+                signatures_valid_until,
+                team_members,
+                deposit,
+                adopted_deletion,
+            })
+        }
+
+        // Returns `None` if the deletion was scheduled for a session that no longer exists
+        pub fn delete_session_from_program(
+            storage: &mut Storage,
+            session_for_account: ActorId,
+            generation: u64,
+        ) -> Result<Option<Event>, SessionError> {
+            if msg::source() != exec::program_id() {
+                return Err(SessionError::MessageOnlyForProgram);
+            }
+
+            // This message is the scheduled deletion itself. It's forgotten only once nothing
+            // can fail anymore, so that an error leaves the state as it was
+            let deletion = storage.scheduled_deletion(&session_for_account, generation);
+            let session_generation =
+                deletion.map_or(generation, |deletion| deletion.session_generation);
+
+            match storage.sessions.get(&session_for_account) {
+                Some(session) if session.generation == session_generation => {
+                    if !session.is_expired() {
+                        // The session was extended on activity, its later deletion is still to come
+                        let other_deletions = storage
+                            .scheduled_deletions
+                            .get(&session_for_account)
+                            .into_iter()
+                            .flatten()
+                            .filter(|deletion| deletion.session_generation == session_generation)
+                            .count()
+                            - usize::from(deletion.is_some());
+                        if other_deletions == 0 {
+                            // Blocks came faster than `ms_per_block`, so the deletion is retried
+                            // at the block the timestamp is now expected to be reached in
+                            let expires_at_block = match session.expires {
+                                0 => session.expires_at_block,
+                                expires => expiration_block(&storage.config, expires)?,
+                            };
+                            schedule_deletion(
+                                storage,
+                                session_for_account,
+                                session_generation,
+                                expires_at_block,
+                            )?;
+                            if let Some(session) = storage.sessions.get_mut(&session_for_account) {
+                                session.expires_at_block = expires_at_block;
+                            }
+                        }
+                        storage.remove_scheduled_deletion(&session_for_account, generation);
+                        return Ok(None);
+                    }
+                    storage.remove_scheduled_deletion(&session_for_account, generation);
+                    if storage.config.notify_owners {
+                        notify_owners(storage, &session_for_account, SessionNotification::Expired);
+                    }
+                    issue_reauth_token(storage, &session_for_account);
+                    storage.remove_session(&session_for_account);
+                    storage.expired_since_heartbeat =
+                        storage.expired_since_heartbeat.saturating_add(1);
+                    // Everything but the events and the reply is done, the margin covers them
+                    if let Some(deletion) = deletion {
+                        let used_gas = deletion.gas.saturating_sub(exec::gas_available());
+                        storage.deletion_gas_baseline = storage.deletion_gas_baseline.max(used_gas);
+                    }
+                    Ok(Some(Event::SessionDeleted))
+                }
+                _ => {
+                    storage.remove_scheduled_deletion(&session_for_account, generation);
+                    Ok(None)
+                }
+            }
+        }
+
+        // Returns `None` until `heartbeat_interval_blocks` have passed since the last heartbeat
+        pub fn heartbeat(storage: &mut Storage) -> Option<Event> {
+            let interval = storage.config.heartbeat_interval_blocks;
+            let block_height = exec::block_height();
+            let next_block = storage.last_heartbeat_block.saturating_add(interval);
+            if interval == 0 || block_height < next_block {
+                return None;
+            }
+            storage.last_heartbeat_block = block_height;
+            Some(Event::SessionsHeartbeat {
+                active: storage.sessions.len() as u32,
+                created_since_last: core::mem::take(&mut storage.created_since_heartbeat),
+                expired_since_last: core::mem::take(&mut storage.expired_since_heartbeat),
+            })
+        }
+
+        // Two-step handshake: the key proposes the session with its own transaction and
+        // the owner confirms it with a call, or signs the same data for `create_session`
+        pub fn request_session(
+            storage: &mut Storage,
+            signature_data: SignatureData,
+        ) -> Result<Event, SessionError> {
+            let key = msg::source();
+            let owner = signature_data.key;
+            if storage.frozen {
+                return Err(SessionError::SessionsAreFrozen);
+            }
+            register_attempt(storage, key)?;
+            let duration = session_duration(&storage.config, &signature_data)?;
+            if duration < storage.config.minimum_session_duration_ms {
+                return Err(SessionError::DurationIsSmall {
+                    provided: duration,
+                    required: storage.config.minimum_session_duration_ms,
+                });
+            }
+            check_allowed_actions(&signature_data.allowed_actions)?;
+            if owner == key {
+                return Err(SessionError::SessionKeyIsOwner);
+            }
+            if storage
+                .blacklisted_keys
+                .get(&owner)
+                .is_some_and(|keys| keys.contains(&key))
+            {
+                return Err(SessionError::KeyIsBlacklisted);
+            }
+
+            let block_height = exec::block_height();
+            storage
+                .session_requests
+                .retain(|_, request| request.valid_until_block >= block_height);
+            storage.session_requests.insert(
+                key,
+                SessionRequest {
+                    owner,
+                    duration,
+                    allowed_actions: signature_data.allowed_actions,
+                    scope_id: signature_data.scope_id,
+                    action_cooldowns: signature_data.action_cooldowns,
+                    valid_until_block: block_height.saturating_add(SESSION_REQUEST_TTL_BLOCKS),
+                },
+            );
+            if storage.config.notify_owners {
+                notify_owners(storage, &owner, SessionNotification::SessionRequested { key });
+            }
+            Ok(Event::SessionRequested { owner, key })
+        }
+
+        pub fn approve_session_request(
+            storage: &mut Storage,
+            key: ActorId,
+        ) -> Result<(ActorId, SessionData), SessionError> {
+            let request = storage
+                .session_requests
+                .get(&key)
+                .filter(|request| request.owner == msg::source())
+                .filter(|request| request.valid_until_block >= exec::block_height())
+                .cloned()
+                .ok_or(SessionError::NoSessionRequest)?;
+            let signature_data = SignatureData {
+                key,
+                duration: request.duration,
+                allowed_actions: request.allowed_actions,
+                scope_id: request.scope_id,
+                action_cooldowns: request.action_cooldowns,
+                duration_blocks: 0,
+                signature_valid_until: 0,
+            };
+            let created =
+                create_approved_session(storage, signature_data, SessionApproval::RequestedByKey)?;
+            storage.session_requests.remove(&key);
+            Ok(created)
+        }
+
+        // Team sessions aren't restored, no single owner can confirm them. Neither are spectator
+        // ones, the owner creates them alone anyway
+        fn issue_reauth_token(storage: &mut Storage, account: &ActorId) {
+            let window = storage.config.reauth_window_blocks;
+            if window == 0
+                || storage.teams.contains_key(account)
+                || storage.spectator_sessions.contains(account)
+            {
+                return;
+            }
+            let Some(session) = storage.sessions.get(account) else {
+                return;
+            };
+            let block_height = exec::block_height();
+            let token = ReauthToken {
+                key: session.key,
+                duration: session.duration,
+                allowed_actions: session.allowed_actions.clone(),
+                scope_id: session.scope_id,
+                action_cooldowns: session.action_cooldowns.clone(),
+                valid_until_block: block_height.saturating_add(window),
+            };
+            storage
+                .reauth_tokens
+                .retain(|_, token| token.valid_until_block >= block_height);
+            storage.reauth_tokens.insert(*account, token);
+        }
+
+        // The same key, actions and duration as the expired session, confirmed by the owner
+        pub fn restore_session(
+            storage: &mut Storage,
+        ) -> Result<(ActorId, SessionData), SessionError> {
+            let token = storage
+                .reauth_tokens
+                .get(&msg::source())
+                .filter(|token| token.valid_until_block >= exec::block_height())
+                .cloned()
+                .ok_or(SessionError::NoReauthToken)?;
+            let signature_data = SignatureData {
+                key: token.key,
+                duration: token.duration,
+                allowed_actions: token.allowed_actions,
+                scope_id: token.scope_id,
+                action_cooldowns: token.action_cooldowns,
+                duration_blocks: 0,
+                signature_valid_until: 0,
+            };
+            create_approved_session(storage, signature_data, SessionApproval::Reauthorization)
+        }
+
+        pub fn cancel_session_request(
+            storage: &mut Storage,
+            key: ActorId,
+        ) -> Result<(), SessionError> {
+            let msg_source = msg::source();
+            match storage.session_requests.get(&key) {
+                Some(request) if msg_source == key || msg_source == request.owner => {
+                    storage.session_requests.remove(&key);
+                    Ok(())
+                }
+                _ => Err(SessionError::NoSessionRequest),
+            }
+        }
+
+        pub fn session_requests(
+            storage: &Storage,
+            owner: &ActorId,
+        ) -> Vec<(ActorId, SessionRequest)> {
+            let block_height = exec::block_height();
+            storage
+                .session_requests
+                .iter()
+                .filter(|(_, request)| {
+                    request.owner == *owner && request.valid_until_block >= block_height
+                })
+                .map(|(key, request)| (*key, request.clone()))
+                .collect()
+        }
+
+        pub fn delete_session_from_account(
+            storage: &mut Storage,
+        ) -> Result<Event, SessionError> {
+            if storage.revoke_session(&msg::source()).is_none() {
+                return Err(SessionError::NoSession);
+            }
+            Ok(Event::SessionDeleted)
+        }
+
+        pub fn blacklist_keys(
+            storage: &mut Storage,
+            keys: Vec<ActorId>,
+        ) -> Result<(), SessionError> {
+            let owner = msg::source();
+            let blacklisted = storage.blacklisted_keys.entry(owner).or_default();
+            blacklisted.extend(keys.iter().copied());
+            if blacklisted.len() > MAX_BLACKLISTED_KEYS {
+                return Err(SessionError::BlacklistIsFull);
+            }
+            if storage
+                .sessions
+                .get(&owner)
+                .is_some_and(|session| keys.contains(&session.key))
+            {
+                storage.revoke_session(&owner);
+            }
+            Ok(())
+        }
+
+        pub fn unblacklist_keys(storage: &mut Storage, keys: Vec<ActorId>) {
+            let owner = msg::source();
+            if let Some(blacklisted) = storage.blacklisted_keys.get_mut(&owner) {
+                for key in &keys {
+                    blacklisted.remove(key);
+                }
+                if blacklisted.is_empty() {
+                    storage.blacklisted_keys.remove(&owner);
+                }
+            }
+        }
+
+        // A label only tells the sessions apart in UIs, it never affects authorization
+        pub fn set_session_label(
+            storage: &mut Storage,
+            session_for_account: ActorId,
+            label: String,
+        ) -> Result<(), SessionError> {
+            if label.len() > MAX_LABEL_LENGTH {
+                return Err(SessionError::LabelIsTooLong);
+            }
+            if !storage.sessions.contains_key(&session_for_account)
+                || !is_owner(storage, &session_for_account, &msg::source())
+            {
+                return Err(SessionError::NoSession);
+            }
+            if label.is_empty() {
+                storage.labels.remove(&session_for_account);
+            } else {
+                storage.labels.insert(session_for_account, label);
+            }
+            Ok(())
+        }
+
+        pub fn update_allowed_actions(
+            storage: &mut Storage,
+            session_for_account: ActorId,
+            add: Vec<$actions_enum>,
+            remove: Vec<$actions_enum>,
+            signature: Option<Vec<u8>>,
+        ) -> Result<Event, SessionError> {
+            let msg_source = msg::source();
+            // The exported snapshot would miss the update
+            if storage.frozen {
+                return Err(SessionError::SessionsAreFrozen);
+            }
+            let session = storage
+                .sessions
+                .get(&session_for_account)
+                .filter(|session| !session.is_expired())
+                .ok_or(SessionError::NoSession)?;
+            if msg_source != session.key && !is_owner(storage, &session_for_account, &msg_source) {
+                return Err(SessionError::NotSessionKey);
+            }
+
+            let mut allowed_actions: Vec<$actions_enum> = session
+                .allowed_actions
+                .iter()
+                .chain(&add)
+                .filter(|action| !remove.contains(action))
+                .cloned()
+                .collect();
+            allowed_actions.sort_by_key(|action| action.encode());
+            allowed_actions.dedup();
+            if allowed_actions.is_empty() {
+                return Err(SessionError::ThereAreNoAllowedMessages);
+            }
+
+            let broadens = allowed_actions
+                .iter()
+                .any(|action| !session.allowed_actions.contains(action));
+            // The key of a spectator session never agreed to more than the read-only actions
+            if broadens
+                && storage.spectator_sessions.contains(&session_for_account)
+                && allowed_actions
+                    .iter()
+                    .any(|action| !read_only_actions().contains(action))
+            {
+                return Err(SessionError::ActionIsNotAllowed);
+            }
+            // Broadening takes the consent of the other side: the owner's for the key,
+            // and the key's for the owner when the key has to consent to the sessions
+            let signer = if !broadens {
+                None
+            } else if msg_source == session.key {
+                Some(session_for_account)
+            } else if msg_source != session_for_account {
+                // A member can't broaden the session of the team for the others
+                return Err(SessionError::ActionIsNotAllowed);
+            } else if storage.config.require_key_signature {
+                Some(session.key)
+            } else {
+                None
+            };
+            let signature_hash = match (signature, signer) {
+                (_, None) => None,
+                (Some(sig_bytes), Some(signer)) => {
+                    let message = ActionsUpdateData {
+                        key: msg_source,
+                        generation: session.generation,
+                        add,
+                        remove,
+                    }
+                    .encode();
+                    Some(check_signature(storage, &signer, &message, &sig_bytes)?)
+                }
+                (None, Some(_)) if msg_source == session.key => {
+                    return Err(SessionError::ActionIsNotAllowed)
+                }
+                (None, Some(_)) => return Err(SessionError::KeySignatureRequired),
+            };
+
+            // Sliding expiration keeps the session past `expires`, so the signature is remembered
+            // for as long as the session can last
+            let remembered_until = session.expires.max(
+                exec::block_timestamp().saturating_add(storage.config.max_session_lifetime_ms),
+            );
+            let size = SessionData {
+                allowed_actions: allowed_actions.clone(),
+                ..session.clone()
+            }
+            .encoded_size();
+            let deposit = storage.deposits.get(&session_for_account);
+            // The whole deposit is returned to one account, so only it can top the deposit up
+            if msg::value() > 0 && deposit.is_some_and(|deposit| deposit.depositor != msg_source) {
+                return Err(SessionError::NotDepositor);
+            }
+            let deposited = deposit.map_or(0, |deposit| deposit.amount);
+            let provided = deposited.saturating_add(msg::value());
+            let required = storage.config.deposit_per_byte.saturating_mul(size as u128);
+            if provided < required {
+                return Err(SessionError::InsufficientDeposit { provided, required });
+            }
+
+            if let Some(signature_hash) = signature_hash {
+                storage.consumed_signatures.insert(signature_hash, remembered_until);
+            }
+            if msg::value() > 0 {
+                storage
+                    .deposits
+                    .entry(session_for_account)
+                    .or_insert(Deposit {
+                        depositor: msg_source,
+                        amount: 0,
+                    })
+                    .amount = provided;
+            }
+            if let Some(session) = storage.sessions.get_mut(&session_for_account) {
+                session.allowed_actions = allowed_actions.clone();
+            }
+            Ok(Event::AllowedActionsUpdated {
+                session_for_account,
+                allowed_actions,
+            })
+        }
+
+        // The durations are the ones the sessions were created for, not counting the extensions
+        // on activity, so that sliding expiration doesn't make them look longer
+        pub fn session_duration_histogram(storage: &Storage) -> DurationHistogram {
+            let buckets = DURATION_HISTOGRAM_BOUNDS_MS.len() + 1;
+            let bucket = |value: u64| {
+                DURATION_HISTOGRAM_BOUNDS_MS
+                    .iter()
+                    .position(|bound| value <= *bound)
+                    .unwrap_or(buckets - 1)
+            };
+            let now = exec::block_timestamp();
+            let mut histogram = DurationHistogram {
+                bounds_ms: DURATION_HISTOGRAM_BOUNDS_MS.to_vec(),
+                durations: vec![0; buckets],
+                remaining: vec![0; buckets],
+            };
+            for session in storage.sessions.values().filter(|session| !session.is_expired()) {
+                histogram.durations[bucket(session.duration)] += 1;
+                histogram.remaining[bucket(session.expires.saturating_sub(now))] += 1;
+            }
+            histogram
+        }
+
+        pub fn sessions_by_label(
+            storage: &Storage,
+            owner: ActorId,
+            label: &str,
+        ) -> Vec<(ActorId, SessionData)> {
+            storage
+                .labels
+                .iter()
+                .filter(|(account, session_label)| {
+                    session_label.as_str() == label && is_owner(storage, account, &owner)
+                })
+                .filter_map(|(account, _)| {
+                    let session = storage.sessions.get(account)?;
+                    Some((*account, session.clone()))
+                })
+                .collect()
+        }
+
+        // Unlike labels, groups are personal: each member can put a team session in its own group
+        pub fn set_session_group(
+            storage: &mut Storage,
+            session_for_account: ActorId,
+            name: String,
+        ) -> Result<(), SessionError> {
+            if name.len() > MAX_GROUP_NAME_LENGTH {
+                return Err(SessionError::GroupNameIsTooLong);
+            }
+            let owner = msg::source();
+            if !storage.sessions.contains_key(&session_for_account)
+                || !is_owner(storage, &session_for_account, &owner)
+            {
+                return Err(SessionError::NoSession);
+            }
+            let groups = storage.session_groups.entry(owner).or_default();
+            groups.retain(|(account, _)| *account != session_for_account);
+            if !name.is_empty() {
+                groups.push((session_for_account, name));
+            }
+            if groups.is_empty() {
+                storage.session_groups.remove(&owner);
+            }
+            Ok(())
+        }
+
+        pub fn revoke_group(storage: &mut Storage, name: String) -> Event {
+            let owner = msg::source();
+            let accounts: Vec<ActorId> = storage
+                .session_groups
+                .get(&owner)
+                .into_iter()
+                .flatten()
+                .filter(|(_, group)| *group == name)
+                .map(|(account, _)| *account)
+                .collect();
+            for account in &accounts {
+                storage.revoke_session(account);
+            }
+            Event::GroupRevoked {
+                owner,
+                name,
+                deleted_sessions: accounts.len() as u32,
+            }
+        }
+
+        pub fn sessions_in_group(
+            storage: &Storage,
+            owner: &ActorId,
+            name: &str,
+        ) -> Vec<(ActorId, SessionData)> {
+            storage
+                .session_groups
+                .get(owner)
+                .into_iter()
+                .flatten()
+                .filter(|(_, group)| group == name)
+                .filter_map(|(account, _)| {
+                    let session = storage.sessions.get(account)?;
+                    Some((*account, session.clone()))
+                })
+                .collect()
+        }
+
+        // The account owns its own session and the sessions of its teams
+        fn is_owner(storage: &Storage, session_for_account: &ActorId, account: &ActorId) -> bool {
+            session_for_account == account
+                || storage
+                    .teams
+                    .get(session_for_account)
+                    .is_some_and(|members| members.contains(account))
+        }
+
+        // Any member can end the session of the team
+        pub fn delete_team_session(
+            storage: &mut Storage,
+            team: ActorId,
+        ) -> Result<Event, SessionError> {
+            let members = storage.teams.get(&team).ok_or(SessionError::NoSession)?;
+            if !members.contains(&msg::source()) {
+                return Err(SessionError::NotTeamMember);
+            }
+            storage.revoke_session(&team);
+            Ok(Event::SessionDeleted)
+        }
+
+        // Shares the checks made in the message with `SessionGuard`, so an action is counted
+        // once however the two are mixed
+        pub fn get_actor_id(
+            storage: &mut Storage,
+            session_for_account: Option<ActorId>,
+            action: &$actions_enum,
+        ) -> Result<ActorId, SessionError> {
+            let Some(account) = session_for_account else {
+                return Ok(msg::source());
+            };
+            authorize_once(storage, account, action.clone())?;
+            Ok(account)
+        }
+
+        // A trusted forwarder vouches for the original sender, which is then treated
+        // as if it had sent the message itself. Only calls made with a session are forwarded:
+        // acting for the original sender itself would take the forwarder at its word
+        // about whose call it is, so the sender has to be checked as the key of the session
+        pub fn get_forwarded_actor_id(
+            storage: &mut Storage,
+            original_sender: ActorId,
+            session_for_account: ActorId,
+            action: &$actions_enum,
+        ) -> Result<ActorId, SessionError> {
+            if !storage.trusted_forwarders.contains(&msg::source()) {
+                return Err(SessionError::UntrustedForwarder);
+            }
+            resolve_actor_id(storage, original_sender, Some(session_for_account), action)
+        }
+
+        // For host methods with several authorization points: the account the message acts for
+        // is resolved once, and each action is checked with the session once per message
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct SessionGuard {
+            owner: ActorId,
+            delegated: bool,
+        }
+
+        impl SessionGuard {
+            // Panics like `get_actor_id` if the sender isn't allowed to send `action`
+            pub fn resolve(session_for_account: Option<ActorId>, action: $actions_enum) -> Self {
+                let guard = Self {
+                    owner: session_for_account.unwrap_or_else(msg::source),
+                    delegated: session_for_account.is_some(),
+                };
+                guard.require(action);
+                guard
+            }
+
+            pub fn owner(&self) -> ActorId {
+                self.owner
+            }
+
+            // Whether the message is sent with a session rather than by the owner itself
+            pub fn is_delegated(&self) -> bool {
+                self.delegated
+            }
+
+            // The owner itself is allowed every action
+            pub fn require(&self, action: $actions_enum) {
+                if self.delegated {
+                    panicking(|| authorize_once(Storage::get_mut(), self.owner, action));
+                }
+            }
+        }
+
+        fn authorize_once(
+            storage: &mut Storage,
+            owner: ActorId,
+            action: $actions_enum,
+        ) -> Result<(), SessionError> {
+            let message_id = msg::id();
+            let cache = unsafe { &mut *core::ptr::addr_of_mut!(GUARD_CACHE) };
+            let cached = cache
+                .as_mut()
+                .filter(|cached| cached.message_id == message_id && cached.owner == owner);
+            if cached
+                .as_ref()
+                .is_some_and(|cached| cached.actions.contains(&action))
+            {
+                return Ok(());
+            }
+            resolve_actor_id(storage, msg::source(), Some(owner), &action)?;
+            match cached {
+                Some(cached) => cached.actions.push(action),
+                None => {
+                    *cache = Some(GuardCache {
+                        message_id,
+                        owner,
+                        actions: vec![action],
+                    })
+                }
+            }
+            Ok(())
+        }
+
+        fn resolve_actor_id(
+            storage: &mut Storage,
+            sender: ActorId,
+            session_for_account: Option<ActorId>,
+            action: &$actions_enum,
+        ) -> Result<ActorId, SessionError> {
+            let Some(account) = session_for_account else {
+                return Ok(sender);
+            };
+            let has_cooldown = check_authorization(storage, &account, &sender, action)?;
+            if storage.config.notify_owners && storage.used_sessions.insert(account) {
+                notify_owners(storage, &account, SessionNotification::FirstUse { key: sender });
+            }
+            extend_session(storage, &account)?;
+            if has_cooldown {
+                let uses = storage.last_action_uses.entry(account).or_default();
+                uses.retain(|(used_action, _)| used_action != action);
+                uses.push((action.clone(), exec::block_height()));
+            }
+            Ok(account)
+        }
+
+        // Every reason `key` can't send `action` for `account`, returns whether the action
+        // has a cooldown in the session
+        fn check_authorization(
+            storage: &Storage,
+            account: &ActorId,
+            key: &ActorId,
+            action: &$actions_enum,
+        ) -> Result<bool, SessionError> {
+            let session = storage.sessions.get(account).ok_or(SessionError::NoSession)?;
+            if session.is_expired() {
+                return Err(SessionError::SessionExpired {
+                    expires_at_block: session.expires_at_block,
+                });
+            }
+            if session.key != *key {
+                return Err(SessionError::NotSessionKey);
+            }
+            if !session.allowed_actions.contains(action) {
+                return Err(SessionError::ActionIsNotAllowed);
+            }
+            if storage.disabled_actions.contains(action) {
+                return Err(SessionError::ActionIsDisabled);
+            }
+            // A freshly phished approval can't be used for the most damaging actions right away
+            if let Some((_, blocks)) = storage
+                .action_timelocks
+                .iter()
+                .find(|(locked_action, _)| locked_action == action)
+            {
+                let usable_from_block = session.created_at_block.saturating_add(*blocks);
+                if exec::block_height() < usable_from_block {
+                    return Err(SessionError::ActionIsTimelocked { usable_from_block });
+                }
+            }
+            match session
+                .action_cooldowns
+                .iter()
+                .find(|(cooling_action, _)| cooling_action == action)
+            {
+                Some((_, blocks)) => {
+                    if let Some((_, last_used_block)) = storage
+                        .last_action_uses
+                        .get(account)
+                        .and_then(|uses| uses.iter().find(|(used_action, _)| used_action == action))
+                    {
+                        let usable_from_block = last_used_block.saturating_add(*blocks);
+                        if exec::block_height() < usable_from_block {
+                            return Err(SessionError::ActionIsCoolingDown { usable_from_block });
+                        }
+                    }
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+
+        // Sliding expiration: the first extension schedules one more deletion at the end of
+        // the maximum lifetime, the earlier ones leave the session alone while it's used
+        fn extend_session(storage: &mut Storage, account: &ActorId) -> Result<(), SessionError> {
+            let config = storage.config;
+            // The session is still usable, it just runs out as it was when frozen
+            if config.sliding_expiration_ms == 0 || storage.frozen {
+                return Ok(());
+            }
+            config.validate()?;
+            let Some(session) = storage.sessions.get_mut(account) else {
+                return Ok(());
+            };
+            let lifetime_blocks =
+                u32::try_from(config.max_session_lifetime_ms / config.ms_per_block)
+                    .unwrap_or(u32::MAX);
+            let until_block = session.created_at_block.saturating_add(lifetime_blocks);
+            let now = exec::block_timestamp();
+            let remaining_blocks = until_block.saturating_sub(exec::block_height());
+            let until =
+                now.saturating_add((remaining_blocks as u64).saturating_mul(config.ms_per_block));
+            let expires = now.saturating_add(config.sliding_expiration_ms).min(until);
+            if expires <= session.expires {
+                return Ok(());
+            }
+            let expires_at_block = expiration_block(&config, expires)?;
+            session.expires = expires;
+            session.expires_at_block = expires_at_block;
+
+            let generation = session.generation;
+            let deletion_scheduled =
+                storage.scheduled_deletions.get(account).is_some_and(|deletions| {
+                    deletions.iter().any(|deletion| {
+                        deletion.session_generation == generation
+                            && deletion.at_block >= expires_at_block
+                    })
+                });
+            if !deletion_scheduled {
+                schedule_deletion(storage, *account, generation, until_block)?;
+                if config.notify_owners {
+                    notify_owners(storage, account, SessionNotification::Extended { until });
+                }
+            }
+            Ok(())
+        }
+
+        pub fn service_info() -> ServiceInfo {
+            use $crate::capabilities::*;
+            let mut capabilities = SR25519_SIGNATURES
+                | KEY_SIGNATURES
+                | TEAM_SESSIONS
+                | TEMPLATES
+                | TRUSTED_FORWARDERS
+                | SLIDING_EXPIRATION
+                | ACTION_COOLDOWNS;
+            if !read_only_actions().is_empty() {
+                capabilities |= SPECTATOR_SESSIONS;
+            }
+            ServiceInfo {
+                version: $crate::VERSION.into(),
+                capabilities,
+            }
+        }
+
+        pub fn pending_deletions(storage: &Storage) -> Vec<PendingDeletion> {
+            let mut pending: Vec<PendingDeletion> = storage
+                .scheduled_deletions
+                .iter()
+                .flat_map(|(account, deletions)| {
+                    let generation =
+                        storage.sessions.get(account).map(|session| session.generation);
+                    deletions.iter().map(move |deletion| PendingDeletion {
+                        account: *account,
+                        at_block: deletion.at_block,
+                        generation: deletion.session_generation,
+                        orphaned: generation != Some(deletion.session_generation),
+                    })
+                })
+                .collect();
+            pending.sort_by_key(|deletion| deletion.at_block);
+            pending
+        }
+
+        pub fn sessions_without_deletion(storage: &Storage) -> Vec<ActorId> {
+            storage
+                .sessions
+                .iter()
+                .filter(|(account, session)| {
+                    !storage.scheduled_deletions.get(*account).is_some_and(|deletions| {
+                        deletions
+                            .iter()
+                            .any(|deletion| deletion.session_generation == session.generation)
+                    })
+                })
+                .map(|(account, _)| *account)
+                .collect()
+        }
+
+        pub fn session_remaining(storage: &Storage, account: &ActorId) -> Option<(u64, u64)> {
+            let session = storage
+                .sessions
+                .get(account)
+                .filter(|session| !session.is_expired())?;
+            let blocks = session.expires_at_block.saturating_sub(exec::block_height()) as u64;
+            // Sessions stored without the timestamp expire with their block
+            let ms = match session.expires {
+                0 => blocks.saturating_mul(storage.config.ms_per_block),
+                expires => expires.saturating_sub(exec::block_timestamp()),
+            };
+            Some((ms, blocks))
+        }
+
+        pub fn gasless_status(storage: &Storage, account: &ActorId) -> GaslessStatus {
+            let session = storage.sessions.get(account).cloned();
+            let usable_actions = session
+                .iter()
+                .filter(|session| !session.is_expired())
+                .flat_map(|session| session.allowed_actions.iter())
+                .filter(|action| !storage.disabled_actions.contains(action))
+                .cloned()
+                .collect();
+            GaslessStatus {
+                session,
+                usable_actions,
+            }
+        }
+
+        // A team is identified by its members, so the same members always share one session
+        pub fn team_id(members: &[ActorId]) -> ActorId {
+            ActorId::from(hash(&[b"team".as_slice(), &members.encode()].concat()))
+        }
+
+        pub fn publish_template(
+            storage: &mut Storage,
+            template: SessionTemplate,
+        ) -> Result<Event, SessionError> {
+            check_admin(storage)?;
+            check_allowed_actions(&template.allowed_actions)?;
+            if template.duration < storage.config.minimum_session_duration_ms {
+                return Err(SessionError::DurationIsSmall {
+                    provided: template.duration,
+                    required: storage.config.minimum_session_duration_ms,
+                });
+            }
+            let template_id = storage.last_template_id + 1;
+            storage.templates.insert(template_id, template);
+            storage.last_template_id = template_id;
+            Ok(Event::TemplatePublished { template_id })
+        }
+
+        // Moves the sessions of a previous program of the game, so that owners don't have
+        // to sign their delegations again. Nothing proves the sessions were delegated there,
+        // so they are taken on the word of the admin, and only while the program is frozen:
+        // no session can be created meanwhile, and the import is visible to everyone.
+        // The expiry timestamps are kept and the blocks are derived from them with the config
+        // of this program. Expired sessions and the ones that couldn't be created here are skipped
+        pub fn import_sessions(
+            storage: &mut Storage,
+            from: ActorId,
+            sessions: Vec<(ActorId, SessionData)>,
+            teams: Vec<(ActorId, Vec<ActorId>)>,
+        ) -> Result<Event, SessionError> {
+            check_admin(storage)?;
+            if !storage.frozen {
+                return Err(SessionError::NotFrozen);
+            }
+            if from.is_zero() || from == exec::program_id() {
+                return Err(SessionError::InvalidImportSource);
+            }
+            let config = storage.config;
+            let teams: HashMap<ActorId, Vec<ActorId>> = teams.into_iter().collect();
+            let mut imported = 0;
+            for (account, session) in sessions {
+                let members = teams.get(&account);
+                let is_invalid_team = members.is_some_and(|members| {
+                    check_team_members(members).is_err()
+                        || team_id(members) != account
+                        || members.contains(&session.key)
+                });
+                if session.expires <= exec::block_timestamp()
+                    || session.key == account
+                    || is_invalid_team
+                    || check_allowed_actions(&session.allowed_actions).is_err()
+                    || session
+                        .scope_id
+                        .is_some_and(|scope_id| storage.invalidated_scopes.contains(&scope_id))
+                    || storage
+                        .blacklisted_keys
+                        .get(&account)
+                        .is_some_and(|keys| keys.contains(&session.key))
+                    || check_if_session_exists(&storage.sessions, &account).is_err()
+                    || check_key_is_free(storage, &account, &session.key).is_err()
+                {
+                    continue;
+                }
+                let expires_at_block = expiration_block(&config, session.expires)?;
+                let generation = storage.last_generation + 1;
+                schedule_deletion(storage, account, generation, expires_at_block)?;
+                storage.insert_session(
+                    account,
+                    SessionData {
+                        expires_at_block,
+                        generation,
+                        ..session
+                    },
+                );
+                if let Some(members) = members {
+                    storage.teams.insert(account, members.clone());
+                }
+                imported += 1;
+            }
+            Ok(Event::SessionsImported { from, imported })
+        }
+
+        pub fn stake_session(
+            storage: &mut Storage,
+            session_for_account: ActorId,
+        ) -> Result<Event, SessionError> {
+            let amount = msg::value();
+            if amount == 0 {
+                return Err(SessionError::NothingToStake);
+            }
+            let session = storage
+                .sessions
+                .get(&session_for_account)
+                .filter(|session| !session.is_expired())
+                .ok_or(SessionError::NoSession)?;
+            if session.key != msg::source() {
+                return Err(SessionError::NotSessionKey);
+            }
+            let stake = storage.stakes.entry(session_for_account).or_insert(Stake {
+                staker: session.key,
+                amount: 0,
+            });
+            stake.amount = stake.amount.saturating_add(amount);
+            Ok(Event::SessionStaked {
+                session_for_account,
+                amount: stake.amount,
+            })
+        }
+
+        // Called by the admin or the arbiter once the misbehavior of the key is proven
+        pub fn slash_stake(
+            storage: &mut Storage,
+            session_for_account: ActorId,
+            recipient: ActorId,
+        ) -> Result<Event, SessionError> {
+            let msg_source = msg::source();
+            if msg_source != storage.admin && Some(msg_source) != storage.arbiter {
+                return Err(SessionError::NotAdmin);
+            }
+            let stake = storage
+                .stakes
+                .remove(&session_for_account)
+                .ok_or(SessionError::NoStake)?;
+            msg::send_bytes(recipient, [], stake.amount)
+                .map_err(|_| SessionError::TransferFailed)?;
+            let reputation = storage.key_reputation.entry(stake.staker).or_default();
+            reputation.slashes = reputation.slashes.saturating_add(1);
+            storage.remove_session(&session_for_account);
+            Ok(Event::StakeSlashed {
+                session_for_account,
+                amount: stake.amount,
+            })
+        }
+
+        pub fn recalibrate_deletion_gas(
+            storage: &mut Storage,
+            baseline: u64,
+        ) -> Result<Event, SessionError> {
+            check_admin(storage)?;
+            storage.deletion_gas_baseline = baseline;
+            Ok(Event::DeletionGasRecalibrated { baseline })
+        }
+
+        pub fn set_arbiter(
+            storage: &mut Storage,
+            arbiter: Option<ActorId>,
+        ) -> Result<(), SessionError> {
+            check_admin(storage)?;
+            storage.arbiter = arbiter;
+            Ok(())
+        }
+
+        // Applies to the existing sessions as well, counting from their creation
+        pub fn set_action_timelock(
+            storage: &mut Storage,
+            action: $actions_enum,
+            blocks: u32,
+        ) -> Result<Event, SessionError> {
+            check_admin(storage)?;
+            storage
+                .action_timelocks
+                .retain(|(locked_action, _)| *locked_action != action);
+            if blocks > 0 {
+                storage.action_timelocks.push((action.clone(), blocks));
+            }
+            Ok(Event::ActionTimelockSet { action, blocks })
+        }
+
+        // For the time the state is exported, so that the snapshot stays valid.
+        // Sessions can still be used and deleted
+        pub fn freeze(storage: &mut Storage) -> Result<Event, SessionError> {
+            check_admin(storage)?;
+            storage.frozen = true;
+            Ok(Event::SessionsFrozen)
+        }
+
+        pub fn unfreeze(storage: &mut Storage) -> Result<Event, SessionError> {
+            check_admin(storage)?;
+            storage.frozen = false;
+            Ok(Event::SessionsUnfrozen)
+        }
+
+        // Neutralizes an exploitable action for all sessions at once, their delegations are kept
+        pub fn disable_action(
+            storage: &mut Storage,
+            action: $actions_enum,
+        ) -> Result<Event, SessionError> {
+            check_admin(storage)?;
+            if !storage.disabled_actions.contains(&action) {
+                storage.disabled_actions.push(action.clone());
+            }
+            Ok(Event::ActionDisabled { action })
+        }
+
+        pub fn enable_action(
+            storage: &mut Storage,
+            action: $actions_enum,
+        ) -> Result<Event, SessionError> {
+            check_admin(storage)?;
+            storage
+                .disabled_actions
+                .retain(|disabled_action| *disabled_action != action);
+            Ok(Event::ActionEnabled { action })
+        }
+
+        pub fn add_trusted_forwarder(
+            storage: &mut Storage,
+            forwarder: ActorId,
+        ) -> Result<Event, SessionError> {
+            check_admin(storage)?;
+            storage.trusted_forwarders.insert(forwarder);
+            Ok(Event::TrustedForwarderAdded { forwarder })
+        }
+
+        pub fn remove_trusted_forwarder(
+            storage: &mut Storage,
+            forwarder: ActorId,
+        ) -> Result<Event, SessionError> {
+            check_admin(storage)?;
+            if !storage.trusted_forwarders.remove(&forwarder) {
+                return Err(SessionError::UntrustedForwarder);
+            }
+            Ok(Event::TrustedForwarderRemoved { forwarder })
+        }
+
+        // Sessions already created from the template are kept, the numbers aren't reused
+        pub fn retire_template(
+            storage: &mut Storage,
+            template_id: u32,
+        ) -> Result<Event, SessionError> {
+            check_admin(storage)?;
+            if storage.templates.remove(&template_id).is_none() {
+                return Err(SessionError::NoTemplate);
+            }
+            Ok(Event::TemplateRetired { template_id })
+        }
+
+        // Deletes every session of the scope, e.g. when the tournament ends,
+        // and rejects any new session for it from now on
+        pub fn invalidate_scope(
+            storage: &mut Storage,
+            scope_id: u64,
+        ) -> Result<Event, SessionError> {
+            check_admin(storage)?;
+            let accounts: Vec<ActorId> = storage
+                .sessions
+                .iter()
+                .filter(|(_, session)| session.scope_id == Some(scope_id))
+                .map(|(account, _)| *account)
+                .collect();
+            for account in &accounts {
+                storage.remove_session(account);
+            }
+            storage.invalidated_scopes.insert(scope_id);
+            Ok(Event::ScopeInvalidated {
+                scope_id,
+                deleted_sessions: accounts.len() as u32,
+            })
+        }
+
+        // The duration in milliseconds, whichever of `duration` and `duration_blocks` is set
+        fn session_duration(
+            config: &Config,
+            signature_data: &SignatureData,
+        ) -> Result<u64, SessionError> {
+            match (signature_data.duration, signature_data.duration_blocks) {
+                (duration, 0) => Ok(duration),
+                (0, blocks) => (blocks as u64)
+                    .checked_mul(config.ms_per_block)
+                    .ok_or(SessionError::DurationIsTooLarge),
+                _ => Err(SessionError::DurationIsAmbiguous),
+            }
+        }
+
+        // The only place where the block counterpart of a session timestamp is derived,
+        // so that `expires` and `expires_at_block` can't drift apart
+        pub fn expiration_block(config: &Config, expires: u64) -> Result<u32, SessionError> {
+            config.validate()?;
+            let remaining = expires.saturating_sub(exec::block_timestamp());
+            u32::try_from(remaining.div_ceil(config.ms_per_block))
+                .ok()
+                .and_then(|number_of_blocks| exec::block_height().checked_add(number_of_blocks))
+                .ok_or(SessionError::DurationIsTooLarge)
+        }
+
+        // The account of the signed path must be an sr25519 public key: a canonical encoding
+        // of a Ristretto point other than the identity, which is encoded as all zeros
+        fn public_key(account: &ActorId) -> Result<PublicKey, SessionError> {
+            let bytes: [u8; 32] = (*account).into();
+            if bytes == [0; 32] {
+                return Err(SessionError::BadPublicKey);
+            }
+            PublicKey::from_bytes(&bytes).map_err(|_| SessionError::BadPublicKey)
+        }
+
+        // The signer signs the same data with `sender` in place of itself,
+        // so each side of the delegation only ever signs the other one
+        fn delegation_message(signature_data: &SignatureData, sender: ActorId) -> Vec<u8> {
+            SignatureData {
+                key: sender,
+                duration: signature_data.duration,
+                allowed_actions: signature_data.allowed_actions.clone(),
+                scope_id: signature_data.scope_id,
+                action_cooldowns: signature_data.action_cooldowns.clone(),
+                duration_blocks: signature_data.duration_blocks,
+                signature_valid_until: signature_data.signature_valid_until,
+            }
+            .encode()
+        }
+
+        // A signed creation is remembered until the end of its validity,
+        // so the validity is limited to keep the remembered ones few
+        fn check_signature_validity(valid_until: u64) -> Result<(), SessionError> {
+            let now = exec::block_timestamp();
+            if valid_until <= now {
+                return Err(SessionError::SignatureExpired);
+            }
+            if valid_until - now > MAX_SIGNATURE_VALIDITY_MS {
+                return Err(SessionError::SignatureValidityIsTooLong);
+            }
+            Ok(())
+        }
+
+        // Returns the hash the signature is remembered by once the session is created
+        fn check_signature(
+            storage: &Storage,
+            signer: &ActorId,
+            message: &[u8],
+            sig_bytes: &[u8],
+        ) -> Result<[u8; 32], SessionError> {
+            let pub_key = public_key(signer)?;
+            let complete_message = [b"<Bytes>".as_slice(), message, b"</Bytes>"].concat();
+
+            let signature = canonical_signature(sig_bytes).ok_or(SessionError::BadSignature)?;
+            let signature_hash = hash(&signature.to_bytes());
+            let now = exec::block_timestamp();
+            if storage
+                .consumed_signatures
+                .get(&signature_hash)
+                .is_some_and(|remembered_until| *remembered_until > now)
+            {
+                return Err(SessionError::SignatureAlreadyUsed);
+            }
+
+            verify(&signature, complete_message, &pub_key)?;
+            Ok(signature_hash)
+        }
+
+        fn verify<M: AsRef<[u8]>>(
+            signature: &Signature,
+            message: M,
+            pub_key: &PublicKey,
+        ) -> Result<(), SessionError> {
+            pub_key
+                .verify_simple(b"substrate", message.as_ref(), signature)
+                .map(|_| ())
+                .map_err(|_| SessionError::VerificationFailed)
+        }
+
+        // Refunds are best-effort, a failed transfer must not block the deletion of a session
+        fn refund(recipient: ActorId, amount: u128) {
+            if amount > 0 {
+                let _ = msg::send_bytes(recipient, [], amount);
+            }
+        }
+
+        // Notifications are best-effort like events. A team is notified through its members,
+        // as its id isn't an account anyone holds
+        fn notify_owners(
+            storage: &Storage,
+            session_for_account: &ActorId,
+            notification: SessionNotification,
+        ) {
+            match storage.teams.get(session_for_account) {
+                Some(members) => {
+                    for member in members {
+                        let _ = msg::send(*member, notification.clone(), 0);
+                    }
+                }
+                None => {
+                    let _ = msg::send(*session_for_account, notification, 0);
+                }
+            }
+        }
+
+        // Runs in the creation exports before anything else. The attempt is counted first,
+        // so that spamming invalid requests is cut off before the registry is asked and
+        // the signatures are verified. The storage isn't borrowed across the replies,
+        // as other messages may change it while they are awaited
+        async fn admit(owners: &[ActorId]) -> Result<(), SessionError> {
+            {
+                let storage = Storage::get_mut();
+                if storage.frozen {
+                    return Err(SessionError::SessionsAreFrozen);
+                }
+                register_attempt(storage, msg::source())?;
+            }
+            check_eligibility(owners).await
+        }
+
+        async fn check_eligibility(owners: &[ActorId]) -> Result<(), SessionError> {
+            let Some(registry) = Storage::get().config.eligibility_registry else {
+                return Ok(());
+            };
+            for owner in owners {
+                let eligible: bool = msg::send_for_reply_as(registry, *owner, 0, 0)
+                    .map_err(|_| SessionError::EligibilityCheckFailed)?
+                    .await
+                    .map_err(|_| SessionError::EligibilityCheckFailed)?;
+                if !eligible {
+                    return Err(SessionError::NotEligible);
+                }
+            }
+            Ok(())
+        }
+
+        // Takes `generation` as the last one and tracks the deletion until it is executed
+        fn schedule_deletion(
+            storage: &mut Storage,
+            account: ActorId,
+            generation: u64,
+            expires_at_block: u32,
+        ) -> Result<(), SessionError> {
+            let request = DeleteSessionFromProgram::encode_call(account, generation);
+            let gas = deletion_gas(storage);
+
+            msg::send_bytes_with_gas_delayed(
+                exec::program_id(),
+                request,
+                gas,
+                0,
+                expires_at_block - exec::block_height(),
+            )
+            .map_err(|_| SessionError::FailedToScheduleDeletion)?;
+
+            storage.last_generation = storage.last_generation.max(generation);
+            storage
+                .scheduled_deletions
+                .entry(account)
+                .or_default()
+                .push(ScheduledDeletion {
+                    generation,
+                    session_generation: generation,
+                    at_block: expires_at_block,
+                    gas,
+                });
+            Ok(())
+        }
+
+        // A session deleted early leaves its message pending, and the unused gas of the message
+        // only returns to the sender once it's executed. Reusing the message for a later
+        // session of the account saves sending another one
+        fn adopt_deletion(
+            storage: &mut Storage,
+            account: ActorId,
+            adopted: ScheduledDeletion,
+            session_generation: u64,
+        ) {
+            storage.last_generation = storage.last_generation.max(session_generation);
+            if let Some(deletion) = storage
+                .scheduled_deletions
+                .get_mut(&account)
+                .and_then(|deletions| {
+                    deletions.iter_mut().find(|deletion| {
+                        deletion.generation == adopted.generation
+                            && deletion.at_block == adopted.at_block
+                    })
+                })
+            {
+                deletion.session_generation = session_generation;
+            }
+        }
+
+        // The measured baseline with the configured margin once a deletion has been executed,
+        // the static `gas_to_delete_session` before that or with no margin configured
+        pub fn deletion_gas(storage: &Storage) -> u64 {
+            let margin = storage.config.deletion_gas_margin_percent;
+            let baseline = storage.deletion_gas_baseline;
+            if margin == 0 || baseline == 0 {
+                return storage.config.gas_to_delete_session;
+            }
+            baseline.saturating_mul(100 + margin as u64) / 100
+        }
+
+        fn register_attempt(storage: &mut Storage, account: ActorId) -> Result<(), SessionError> {
+            let limit = storage.config.max_attempts_per_block;
+            if limit == 0 {
+                return Ok(());
+            }
+
+            let block_height = exec::block_height();
+            if storage.attempts_block != block_height {
+                storage.attempts_block = block_height;
+                storage.attempts.clear();
+            }
+
+            check_attempts(storage, &account)?;
+            *storage.attempts.entry(account).or_default() += 1;
+            Ok(())
+        }
+
+        fn check_attempts(storage: &Storage, account: &ActorId) -> Result<(), SessionError> {
+            let limit = storage.config.max_attempts_per_block;
+            let attempts = if storage.attempts_block == exec::block_height() {
+                storage.attempts.get(account).copied().unwrap_or_default()
+            } else {
+                0
+            };
+            if limit != 0 && attempts >= limit {
+                return Err(SessionError::TooManyAttempts { limit });
+            }
+            Ok(())
+        }
+
+        fn check_admin(storage: &Storage) -> Result<(), SessionError> {
+            if msg::source() != storage.admin {
+                return Err(SessionError::NotAdmin);
+            }
+            Ok(())
+        }
+
+        // Members are listed in ascending order without duplicates, so that a team has one id,
+        // and each of them provides the signature at the same position
+        fn check_team(
+            members: &[ActorId],
+            signatures: &[Vec<u8>],
+            key: &ActorId,
+        ) -> Result<(), SessionError> {
+            check_team_members(members)?;
+            if signatures.len() != members.len() {
+                return Err(SessionError::InvalidTeam);
+            }
+            if members.contains(key) {
+                return Err(SessionError::SessionKeyIsOwner);
+            }
+            Ok(())
+        }
+
+        fn check_team_members(members: &[ActorId]) -> Result<(), SessionError> {
+            if members.len() < 2
+                || members.len() > MAX_TEAM_SIZE
+                || members.windows(2).any(|pair| pair[0] >= pair[1])
+            {
+                return Err(SessionError::InvalidTeam);
+            }
+            Ok(())
+        }
+
+        // Allowed actions must be listed in the canonical form: ordered by their encoding
+        // and without duplicates. The stored list is then exactly the one that was signed,
+        // so the signed message can always be rebuilt from the session data.
+        fn check_allowed_actions(allowed_actions: &[$actions_enum]) -> Result<(), SessionError> {
+            if allowed_actions.is_empty() {
+                return Err(SessionError::ThereAreNoAllowedMessages);
+            }
+            let is_canonical = allowed_actions
+                .windows(2)
+                .all(|pair| pair[0].encode() < pair[1].encode());
+            if !is_canonical {
+                return Err(SessionError::AllowedActionsAreNotCanonical);
+            }
+            Ok(())
+        }
+
+        // A session key can act for a single account only and can't be an account
+        // with its own active session, otherwise the key -> account lookup is ambiguous
+        fn check_key_is_free(
+            storage: &Storage,
+            account: &ActorId,
+            key: &ActorId,
+        ) -> Result<(), SessionError> {
+            let is_active = |account: &ActorId| {
+                storage
+                    .sessions
+                    .get(account)
+                    .is_some_and(|session| !session.is_expired())
+            };
+            let key_acts_for_another_account = storage
+                .keys
+                .get(key)
+                .is_some_and(|owner| owner != account && is_active(owner));
+            let account_is_a_key = storage.keys.get(account).is_some_and(is_active);
+
+            if key_acts_for_another_account || is_active(key) || account_is_a_key {
+                return Err(SessionError::KeyAlreadyInUse);
+            }
+            Ok(())
+        }
+
+        fn check_if_session_exists(
+            session_map: &HashMap<ActorId, SessionData>,
+            account: &ActorId,
+        ) -> Result<(), SessionError> {
+            if let Some(session) = session_map.get(account) {
+                if !session.is_expired() {
+                    return Err(SessionError::AlreadyHaveActiveSession {
+                        current_block: exec::block_height(),
+                        expires_at_block: session.expires_at_block,
+                    });
                 }
             }
             Ok(())
@@ -1,4 +1,5 @@
 pub use gstd::{ext, fmt::Debug, format};
+use schnorrkel::{points::RistrettoBoth, signing_context, Signature, SIGNATURE_LENGTH};
 
 pub fn panicking<T, E: Debug, F: FnOnce() -> Result<T, E>>(f: F) -> T {
     match f() {
@@ -10,3 +11,23 @@ pub fn panicking<T, E: Debug, F: FnOnce() -> Result<T, E>>(f: F) -> T {
 pub fn panic(err: impl Debug) -> ! {
     ext::panic(&format!("{err:?}"))
 }
+
+pub fn hash(data: &[u8]) -> [u8; 32] {
+    let mut transcript = signing_context(b"session-service").bytes(data);
+    let mut hash = [0; 32];
+    transcript.challenge_bytes(b"hash", &mut hash);
+    hash
+}
+
+// Parses an sr25519 signature accepting only its canonical encoding:
+// the schnorrkel marker bit set, a reduced scalar `s` and a canonically
+// encoded point `R`. Any other byte string of a valid signature is rejected,
+// so one approval can't be replayed under a different representation.
+pub fn canonical_signature(bytes: &[u8]) -> Option<Signature> {
+    if bytes.len() != SIGNATURE_LENGTH {
+        return None;
+    }
+    let signature = Signature::from_bytes(bytes).ok()?;
+    RistrettoBoth::from_bytes(&bytes[..32]).ok()?;
+    (signature.to_bytes()[..] == *bytes).then_some(signature)
+}